@@ -0,0 +1,47 @@
+//! Benchmarks rendering a schema with thousands of fields
+
+use capnp_model::{CapnpType, Field, Schema, SchemaItem, Struct, Union, UnionVariant};
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+
+/// Builds `structs` structs with `fields` fields each, plus a union of `Option`s so
+/// that helper structs are rendered too
+fn large_schema(structs: usize, fields: u32) -> Schema {
+    let mut schema = Schema::new();
+    for i in 0..structs {
+        let mut s = Struct::new(format!("Type{}", i));
+        for id in 0..fields {
+            let field_type = match id % 4 {
+                0 => CapnpType::UInt64,
+                1 => CapnpType::Text,
+                2 => CapnpType::List(Box::new(CapnpType::Float32)),
+                _ => CapnpType::UserDefined(format!("Type{}", (i + 1) % structs)),
+            };
+            s.add_field(Field::new(format!("field{}", id), id, field_type));
+        }
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new(
+            "none".to_string(),
+            fields,
+            CapnpType::Void,
+        ));
+        union.add_variant(UnionVariant::new(
+            "some".to_string(),
+            fields + 1,
+            CapnpType::Option(Box::new(CapnpType::Int32)),
+        ));
+        s.set_union(union);
+        schema.add_item(SchemaItem::Struct(s));
+    }
+    schema
+}
+
+fn bench_render(c: &mut Criterion) {
+    let schema = large_schema(100, 50);
+    c.bench_function("render 100 structs x 50 fields", |b| {
+        b.iter(|| black_box(&schema).render().unwrap())
+    });
+}
+
+criterion_group!(benches, bench_render);
+criterion_main!(benches);
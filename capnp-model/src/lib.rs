@@ -2,46 +2,421 @@
 //!
 //! This module defines data structures that represent Cap'n Proto schemas
 //! in an abstract way, separate from the string generation logic.
+//!
+//! The crate is `no_std` and only needs `alloc`.
+
+#![no_std]
 
-use std::fmt::Write;
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
 
 /// Error type for Cap'n Proto model validation
 #[derive(Debug, Clone, PartialEq)]
 pub enum ValidationError {
-    DuplicateId { id: u32, locations: Vec<String> },
+    /// `type_name` is the struct or interface the IDs are in
+    DuplicateId {
+        type_name: String,
+        id: u32,
+        locations: Vec<String>,
+    },
+    DuplicateTypeName {
+        name: String,
+    },
+    /// `type_name` is the struct the field is in, or empty for a standalone field
+    InvalidDefault {
+        type_name: String,
+        field: String,
+        reason: String,
+    },
+    InvalidTypeId {
+        name: String,
+        id: u64,
+    },
+    InvalidFileId {
+        id: u64,
+    },
+    MultipleAnonymousUnions {
+        type_name: String,
+    },
+    /// `union_name` is `None` for the anonymous union; `missing` is the lowest ordinal
+    /// in the union's range that nothing in the struct uses
+    UnionOrdinalGap {
+        type_name: String,
+        union_name: Option<String>,
+        missing: u32,
+    },
+    /// Only reported by `Schema::validate_capnp_compatible`; `missing` is the lowest ID
+    /// below the struct's highest that nothing uses
+    IdGap {
+        type_name: String,
+        missing: u32,
+    },
+    /// Only reported by `Schema::validate_capnp_compatible`; `type_name` is the type
+    /// containing the name, or empty for a top-level type's own name
+    InvalidName {
+        type_name: String,
+        name: String,
+        reason: String,
+    },
+    /// Only reported when rendering with `RenderOptions::forbid_empty_structs`
+    EmptyStruct {
+        name: String,
+    },
+    UndefinedType {
+        name: String,
+        referenced_by: String,
+    },
 }
 
-impl std::fmt::Display for ValidationError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ValidationError::DuplicateId { id, locations } => {
+            ValidationError::DuplicateId {
+                type_name,
+                id,
+                locations,
+            } => {
+                write_type_prefix(f, type_name)?;
                 write!(f, "Duplicate ID {} found in: {}", id, locations.join(", "))
             }
+            ValidationError::DuplicateTypeName { name } => {
+                write!(f, "Type '{}' is defined more than once", name)
+            }
+            ValidationError::InvalidDefault {
+                type_name,
+                field,
+                reason,
+            } => {
+                write_type_prefix(f, type_name)?;
+                write!(f, "Invalid default value for field '{}': {}", field, reason)
+            }
+            ValidationError::UndefinedType {
+                name,
+                referenced_by,
+            } => {
+                write!(
+                    f,
+                    "Type '{}' referenced by '{}' is not defined",
+                    name, referenced_by
+                )
+            }
+            ValidationError::EmptyStruct { name } => {
+                write!(
+                    f,
+                    "Struct '{}' has no fields; check for missing capnp(id) attributes",
+                    name
+                )
+            }
+            ValidationError::MultipleAnonymousUnions { type_name } => {
+                write!(
+                    f,
+                    "Struct '{}' has more than one anonymous union; name all but one",
+                    type_name
+                )
+            }
+            ValidationError::UnionOrdinalGap {
+                type_name,
+                union_name,
+                missing,
+            } => {
+                write_type_prefix(f, type_name)?;
+                match union_name {
+                    Some(name) => write!(f, "Union '{}'", name)?,
+                    None => write!(f, "Union")?,
+                }
+                write!(
+                    f,
+                    " skips ordinal {}; union ordinals can't have gaps",
+                    missing
+                )
+            }
+            ValidationError::IdGap { type_name, missing } => {
+                write_type_prefix(f, type_name)?;
+                write!(
+                    f,
+                    "ID {} is unused; keep removed fields as extra fields so IDs have no gaps",
+                    missing
+                )
+            }
+            ValidationError::InvalidName {
+                type_name,
+                name,
+                reason,
+            } => {
+                write_type_prefix(f, type_name)?;
+                write!(f, "Invalid name '{}': {}", name, reason)
+            }
+            ValidationError::InvalidFileId { id } => {
+                write!(
+                    f,
+                    "File ID {:#x} must have its high bit set (e.g. {:#x})",
+                    id,
+                    with_high_bit(*id)
+                )
+            }
+            ValidationError::InvalidTypeId { name, id } => {
+                write!(
+                    f,
+                    "Type ID {:#x} of '{}' must have its high bit set",
+                    id, name
+                )
+            }
+        }
+    }
+}
+
+/// Writes `In 'Type': `, naming the type an error was found in, if known
+fn write_type_prefix(f: &mut fmt::Formatter<'_>, type_name: &str) -> fmt::Result {
+    if type_name.is_empty() {
+        Ok(())
+    } else {
+        write!(f, "In '{}': ", type_name)
+    }
+}
+
+impl core::error::Error for ValidationError {}
+
+/// A non-fatal problem found by `validate_warnings`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationWarning {
+    /// An ID far beyond the number of IDs in use by its struct
+    SuspiciousId {
+        type_name: String,
+        id: u32,
+        location: String,
+    },
+    /// Fields, in declaration order, that rendering by ID moves to a different position;
+    /// only reported with `RenderOptions::warn_on_reorder`
+    FieldsReordered {
+        type_name: String,
+        fields: Vec<String>,
+    },
+    /// A `List(UInt8)`, which is almost always meant to be `Data`; only reported with
+    /// `RenderOptions::warn_on_byte_lists`
+    ByteList { type_name: String, location: String },
+}
+
+impl fmt::Display for ValidationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationWarning::SuspiciousId {
+                type_name,
+                id,
+                location,
+            } => write!(
+                f,
+                "ID {} of {} in '{}' is far beyond the number of IDs in use",
+                id, location, type_name
+            ),
+            ValidationWarning::FieldsReordered { type_name, fields } => write!(
+                f,
+                "Fields {} of '{}' are declared out of ID order and render in a different \
+                 position",
+                fields.join(", "),
+                type_name
+            ),
+            ValidationWarning::ByteList {
+                type_name,
+                location,
+            } => write!(
+                f,
+                "The type of {} in '{}' contains List(UInt8); use Data for bytes",
+                location, type_name
+            ),
         }
     }
 }
 
-impl std::error::Error for ValidationError {}
+/// How far beyond the number of IDs in a struct an ID can be before it's reported
+/// as suspicious
+const SUSPICIOUS_ID_GAP: u64 = 32;
 
 /// Represents a complete Cap'n Proto schema document
 #[derive(Debug, Clone, PartialEq)]
 pub struct Schema {
     pub items: Vec<SchemaItem>,
+    pub field_order: FieldOrder,
+    /// Whether [`Schema::validate`] accepts references to types the document doesn't
+    /// define, e.g. because they live in an imported file
+    pub allow_undefined_types: bool,
+    /// File-level annotations for code generators, rendered before the items
+    pub language_annotations: Vec<(LanguageAnnotation, String)>,
+    /// Types defined in other schema files, by name, with the path they're imported from
+    pub external_types: BTreeMap<String, String>,
+    /// A version number for tooling, rendered as a comment at the top of the document
+    pub version: Option<u32>,
+}
+
+/// A file-level annotation read by a Cap'n Proto code generator plugin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LanguageAnnotation {
+    /// `$Cxx.namespace("...")`: the C++ namespace of the generated code
+    CxxNamespace,
+    /// `$Go.package("...")`: the Go package name of the generated code
+    GoPackage,
+}
+
+impl LanguageAnnotation {
+    /// The `using` line importing the annotation's definitions
+    fn import(self) -> &'static str {
+        match self {
+            LanguageAnnotation::CxxNamespace => "using Cxx = import \"/capnp/c++.capnp\";",
+            LanguageAnnotation::GoPackage => "using Go = import \"/go.capnp\";",
+        }
+    }
+
+    /// The annotation as applied to the file, e.g. `$Cxx.namespace`
+    fn application(self) -> &'static str {
+        match self {
+            LanguageAnnotation::CxxNamespace => "$Cxx.namespace",
+            LanguageAnnotation::GoPackage => "$Go.package",
+        }
+    }
+}
+
+/// Controls the order in which struct and group fields are rendered
+///
+/// The default is [`FieldOrder::ById`], so the same type renders identically
+/// regardless of how its fields were declared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FieldOrder {
+    /// Fields are rendered in the order they were added
+    SourceOrder,
+    /// Fields are rendered sorted by their ID
+    #[default]
+    ById,
+}
+
+/// Options controlling the layout of rendered schema text
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    /// One level of indentation, repeated for each nesting level (two spaces by default)
+    pub indent: String,
+    /// Whether to separate adjacent union group variants with a blank line
+    pub blank_lines_between_groups: bool,
+    /// Whether to append `# rust: name` to fields whose Rust name differs from their
+    /// Cap'n Proto name
+    pub rust_name_comments: bool,
+    /// Whether to fail with `ValidationError::EmptyStruct` for structs with no fields,
+    /// groups, unions or extra fields, unless they set `allow_empty`
+    pub forbid_empty_structs: bool,
+    /// Whether [`Schema::validate_warnings_with`] reports fields that rendering by ID
+    /// moves away from their declaration order
+    pub warn_on_reorder: bool,
+    /// Whether [`Schema::validate_warnings_with`] reports `List(UInt8)` types, which
+    /// are almost always meant to be `Data`
+    pub warn_on_byte_lists: bool,
 }
 
 /// Top-level items in a Cap'n Proto schema
 #[derive(Debug, Clone, PartialEq)]
 pub enum SchemaItem {
     Struct(Struct),
+    Enum(Enum),
+    Interface(Interface),
+    Transparent(Transparent),
+}
+
+/// The kind of type a user-defined reference points at
+///
+/// Rust enums derived with `CapnpType` render as structs containing a union, but are
+/// marked with [`Struct::is_enum`], so references to them are of kind
+/// [`TypeKind::Enum`] like those to native enums.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeKind {
+    Struct,
+    Enum,
+    Interface,
+}
+
+/// Represents a native Cap'n Proto enum, whose enumerants carry no data
+///
+/// The derive renders Rust enums as structs containing a union instead; see
+/// [`SchemaItem::try_into_native_enum`] to convert one whose variants are all `Void`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enum {
+    pub name: String,
+    pub enumerants: Vec<Enumerant>,
+}
+
+/// Represents a named value of an enum, e.g. `active @0;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enumerant {
+    pub name: String,
+    pub id: u32,
+}
+
+/// Represents a transparent newtype, which stands in for its target type
+///
+/// A transparent item doesn't define a Cap'n Proto type of its own: when rendered as
+/// part of a [`Schema`], references to it are replaced with the target type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transparent {
+    pub name: String,
+    pub target: CapnpType,
+}
+
+/// Represents a Cap'n Proto interface (RPC) definition
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    pub methods: Vec<Method>,
+}
+
+/// Represents a method of an interface, e.g.
+/// `evaluate @0 (expr :Expression) -> (value :Float64);`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Method {
+    pub name: String,
+    pub id: u32,
+    pub params: Vec<Param>,
+    pub results: Vec<Param>,
+}
+
+/// Represents a named parameter or result of an interface method
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub param_type: CapnpType,
 }
 
 /// Represents a Cap'n Proto struct definition
 #[derive(Debug, Clone, PartialEq)]
 pub struct Struct {
     pub name: String,
+    /// Explicit Cap'n Proto type ID, rendered as `struct Foo @0x... {`
+    pub type_id: Option<u64>,
+    pub generic_params: Vec<String>,
     pub fields: Vec<Field>,
-    pub union: Option<Union>,
+    pub groups: Vec<Group>,
+    /// Unions sharing the struct's ID space; at most one may be anonymous
+    pub unions: Vec<Union>,
     pub extra_fields: Vec<String>,
+    /// Lines copied verbatim into the struct body after everything else, without any
+    /// validation
+    pub raw_lines: Vec<String>,
+    /// Types declared inside this struct, rendered before its fields
+    pub nested: Vec<SchemaItem>,
+    /// Exempts the struct from `RenderOptions::forbid_empty_structs`
+    pub allow_empty: bool,
+    /// Whether the struct was derived from a Rust enum, making it a [`TypeKind::Enum`]
+    pub is_enum: bool,
+}
+
+/// Represents a named (non-union) group of fields within a struct
+///
+/// Group members share the enclosing struct's ID space.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub fields: Vec<Field>,
 }
 
 /// Represents a field in a Cap'n Proto struct
@@ -50,11 +425,32 @@ pub struct Field {
     pub name: String,
     pub id: u32,
     pub field_type: CapnpType,
+    pub default: Option<DefaultValue>,
+    /// The original Rust field name, if it differs from `name`
+    pub rust_name: Option<String>,
+    /// Whether the field is kept only to reserve its ID, rendered with a
+    /// `# deprecated` comment
+    pub deprecated: bool,
+    /// The unit a time value is counted in (e.g. `millis`), rendered as a comment
+    pub time_unit: Option<String>,
+}
+
+/// Represents the default value of a field (e.g. `count @0 :UInt32 = 10;`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefaultValue {
+    Int(i64),
+    /// An unsigned integer above `i64::MAX`, which only fits a `UInt64`
+    UInt(u64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
 }
 
 /// Represents a union within a Cap'n Proto struct
 #[derive(Debug, Clone, PartialEq)]
 pub struct Union {
+    /// Renders the union as `name :union { ... }` rather than an anonymous `union { ... }`
+    pub name: Option<String>,
     pub variants: Vec<UnionVariant>,
 }
 
@@ -72,8 +468,27 @@ pub enum UnionVariantInner {
     Group(Vec<Field>),
 }
 
+/// Where an ID lives within a struct's ID space, as found by [`Struct::field_by_id`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdLocation<'a> {
+    Field(&'a Field),
+    GroupMember {
+        group: &'a Group,
+        field: &'a Field,
+    },
+    UnionVariant(&'a UnionVariant),
+    UnionGroupMember {
+        variant: &'a UnionVariant,
+        field: &'a Field,
+    },
+}
+
 /// Represents Cap'n Proto types
-#[derive(Debug, Clone, PartialEq)]
+///
+/// Types are ordered by kind in declaration order (primitives first, then lists,
+/// options, results, user-defined types and generic parameters), and then by their
+/// element types or names.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum CapnpType {
     // Primitive types
     Bool,
@@ -88,19 +503,38 @@ pub enum CapnpType {
     Float32,
     Float64,
     Text,
+    Data,
     Void,
 
     // Complex types
     List(Box<CapnpType>),
 
+    // An optional value, rendered as a reference to a generated helper struct
+    // (e.g. `OptionUInt32`) holding a `none`/`some` union
+    Option(Box<CapnpType>),
+
+    // A success or error value, rendered as a reference to a generated helper struct
+    // (e.g. `ResultUInt32Text`) holding an `ok`/`err` union
+    Result(Box<CapnpType>, Box<CapnpType>),
+
     // User-defined types (referenced by name)
     UserDefined(String),
+
+    // A generic parameter of the enclosing struct (e.g. `T` in `struct Envelope(T)`)
+    GenericParam(String),
 }
 
 impl Schema {
     /// Creates a new empty document
     pub fn new() -> Self {
-        Self { items: Vec::new() }
+        Self {
+            items: Vec::new(),
+            field_order: FieldOrder::default(),
+            allow_undefined_types: false,
+            language_annotations: Vec::new(),
+            external_types: BTreeMap::new(),
+            version: None,
+        }
     }
 
     /// Adds an item to the document
@@ -112,353 +546,3661 @@ impl Schema {
     pub fn with_struct(capnp_struct: Struct) -> Self {
         Self {
             items: vec![SchemaItem::Struct(capnp_struct)],
+            field_order: FieldOrder::default(),
+            allow_undefined_types: false,
+            language_annotations: Vec::new(),
+            external_types: BTreeMap::new(),
+            version: None,
         }
     }
 
-    /// Validates all structs in the document for ID conflicts
-    pub fn validate(&self) -> Result<(), ValidationError> {
+    /// Adds a file-level annotation for a language's code generator, e.g. the C++
+    /// namespace, rendered with the `using` import it needs before all items
+    pub fn add_language_annotation(&mut self, kind: LanguageAnnotation, value: String) {
+        self.language_annotations.push((kind, value));
+    }
+
+    /// Tags the document with a version number, rendered as a `# schema version: N`
+    /// comment before everything else
+    pub fn set_version(&mut self, version: u32) {
+        self.version = Some(version);
+    }
+
+    /// Declares a type defined in another schema file, so that references to it count
+    /// as defined; it's rendered as `using Name = import "path".Name;`
+    pub fn declare_external(&mut self, name: &str, import_path: &str) {
+        self.external_types
+            .insert(name.to_string(), import_path.to_string());
+    }
+
+    /// Splits the document into several files, putting each item in the file named by
+    /// `file_for`, and returns the files sorted by name
+    ///
+    /// References to items that ended up in another file are declared as external
+    /// types imported from that file. Every file keeps the document's settings and
+    /// language annotations, and the document's external types that it references.
+    pub fn partition(&self, file_for: impl Fn(&SchemaItem) -> String) -> Vec<(String, Schema)> {
+        let mut files: BTreeMap<String, Schema> = BTreeMap::new();
+        let mut file_of_type = BTreeMap::new();
         for item in &self.items {
-            match item {
-                SchemaItem::Struct(s) => s.validate()?,
+            let file = file_for(item);
+            file_of_type.insert(item.name().to_string(), file.clone());
+            files
+                .entry(file)
+                .or_insert_with(|| Schema {
+                    items: Vec::new(),
+                    field_order: self.field_order,
+                    allow_undefined_types: self.allow_undefined_types,
+                    language_annotations: self.language_annotations.clone(),
+                    external_types: BTreeMap::new(),
+                    version: self.version,
+                })
+                .add_item(item.clone());
+        }
+
+        for (file, schema) in &mut files {
+            let mut references = BTreeSet::new();
+            for item in &schema.items {
+                item.collect_references(&mut references);
+            }
+            for name in references {
+                if let Some(other_file) = file_of_type.get(&name) {
+                    if other_file != file {
+                        schema.declare_external(&name, other_file);
+                    }
+                } else if let Some(import_path) = self.external_types.get(&name) {
+                    schema.declare_external(&name, import_path);
+                }
             }
         }
-        Ok(())
-    }
 
-    /// Renders the document as Cap'n Proto schema text
-    /// Automatically validates all structs before rendering
-    pub fn render(&self) -> Result<String, ValidationError> {
-        // Validate before rendering
-        self.validate()?;
+        files.into_iter().collect()
+    }
 
-        let mut output = String::new();
+    /// Sets the order in which fields are rendered
+    pub fn set_field_order(&mut self, field_order: FieldOrder) {
+        self.field_order = field_order;
+    }
 
-        for (i, item) in self.items.iter().enumerate() {
-            if i > 0 {
-                writeln!(&mut output).unwrap();
+    /// Creates a document containing the named root items and, transitively, every
+    /// item from `pool` that they reference
+    ///
+    /// Each item is emitted exactly once, so recursive and mutually recursive types
+    /// are handled. References to names not present in `pool` are ignored.
+    pub fn with_dependencies(roots: &[&str], pool: &[SchemaItem]) -> Self {
+        let mut schema = Self::new();
+        let mut visited = BTreeSet::new();
+        let mut pending: Vec<String> = roots.iter().rev().map(|r| r.to_string()).collect();
+
+        while let Some(name) = pending.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
             }
-            write!(&mut output, "{}", item.render()?).unwrap();
+            let Some(item) = pool.iter().find(|item| item.name() == name) else {
+                continue;
+            };
+
+            let mut references = BTreeSet::new();
+            item.collect_references(&mut references);
+            pending.extend(references.into_iter().rev());
+
+            schema.add_item(item.clone());
         }
 
-        Ok(output)
+        schema
     }
-}
 
-impl SchemaItem {
-    /// Renders the item as Cap'n Proto schema text
-    pub fn render(&self) -> Result<String, ValidationError> {
-        match self {
-            SchemaItem::Struct(s) => s.render(),
+    /// Moves the top-level item named `child` inside the struct named `parent`
+    ///
+    /// References to the child from other top-level items are rewritten to
+    /// `Parent.Child`, since the bare name is only in scope inside the parent. Returns
+    /// false, leaving the document unchanged, if either is missing or the parent isn't a
+    /// struct.
+    pub fn nest(&mut self, child: &str, parent: &str) -> bool {
+        let parent_is_struct = self
+            .items
+            .iter()
+            .any(|item| matches!(item, SchemaItem::Struct(s) if s.name == parent));
+        let Some(index) = self.items.iter().position(|item| item.name() == child) else {
+            return false;
+        };
+        if !parent_is_struct || child == parent {
+            return false;
         }
-    }
-}
 
-impl Struct {
-    /// Creates a new struct with the given name
-    pub fn new(name: String) -> Self {
-        Self {
-            name,
-            fields: Vec::new(),
-            union: None,
-            extra_fields: Vec::new(),
+        let item = self.items.remove(index);
+        let qualified = format!("{}.{}", parent, child);
+        for existing in &mut self.items {
+            match existing {
+                SchemaItem::Struct(s) if s.name == parent => s.add_nested(item.clone()),
+                _ => existing.for_each_type_mut(&mut |capnp_type| {
+                    capnp_type.rename(child, &qualified);
+                }),
+            }
         }
+        true
     }
 
-    /// Adds a field to the struct
-    pub fn add_field(&mut self, field: Field) {
-        self.fields.push(field);
-    }
+    /// Renames a type, updating its definition and every reference to it, and returns
+    /// the number of references updated
+    ///
+    /// Fails with `ValidationError::DuplicateTypeName` if a type named `to` already
+    /// exists.
+    pub fn rename_type(&mut self, from: &str, to: &str) -> Result<usize, ValidationError> {
+        let mut names = BTreeSet::new();
+        for item in &self.items {
+            item.collect_names(&mut names);
+        }
+        names.extend(self.external_types.keys().map(String::as_str));
+        if names.contains(to) {
+            return Err(ValidationError::DuplicateTypeName {
+                name: to.to_string(),
+            });
+        }
 
-    /// Sets the union for this struct
-    pub fn set_union(&mut self, union: Union) {
-        self.union = Some(union);
+        let mut renamed = 0;
+        for item in &mut self.items {
+            item.rename_definition(from, to);
+            item.for_each_type_mut(&mut |capnp_type| renamed += capnp_type.rename(from, to));
+        }
+        Ok(renamed)
     }
 
-    /// Adds an extra field (for backwards compatibility)
-    pub fn add_extra_field(&mut self, extra_field: String) {
-        self.extra_fields.push(extra_field);
+    /// Returns the number of items in the document
+    pub fn len(&self) -> usize {
+        self.items.len()
     }
 
-    /// Validates that all IDs in the struct are unique
-    /// This includes regular field IDs, union variant IDs, and union group field IDs
-    pub fn validate(&self) -> Result<(), ValidationError> {
-        let mut id_locations: std::collections::HashMap<u32, Vec<String>> =
-            std::collections::HashMap::new();
-
-        // Collect regular field IDs
-        for field in &self.fields {
-            let location = format!("struct field '{}'", field.name);
-            id_locations.entry(field.id).or_default().push(location);
-        }
+    /// Whether the document has no items
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
 
-        // Collect union group field IDs if union exists
-        if let Some(union) = &self.union {
-            for variant in &union.variants {
-                match &variant.variant_inner {
-                    UnionVariantInner::Type { id: variant_id, .. } => {
-                        let location = format!("union variant '{}'", variant.name);
-                        id_locations.entry(*variant_id).or_default().push(location);
-                    }
+    /// Iterates over the document's structs, skipping interfaces and transparent items
+    pub fn structs(&self) -> impl Iterator<Item = &Struct> {
+        self.items.iter().filter_map(|item| match item {
+            SchemaItem::Struct(s) => Some(s),
+            _ => None,
+        })
+    }
 
-                    // If this variant is a group, collect its field IDs too
-                    UnionVariantInner::Group(fields) => {
-                        for field in fields {
-                            let location =
-                                format!("union group '{}' field '{}'", variant.name, field.name);
-                            id_locations.entry(field.id).or_default().push(location);
-                        }
-                    }
-                }
-            }
+    /// Returns the names of all user-defined types referenced by the document's items,
+    /// including through lists, options, groups and unions, but not external types
+    pub fn referenced_types(&self) -> BTreeSet<String> {
+        let mut references = BTreeSet::new();
+        for item in &self.items {
+            item.collect_references(&mut references);
         }
+        references.retain(|name| !self.external_types.contains_key(name));
+        references
+    }
 
-        // Check for duplicates
-        for (id, locations) in id_locations {
-            if locations.len() > 1 {
-                return Err(ValidationError::DuplicateId { id, locations });
+    /// Returns the kind of the type a `UserDefined` reference with this name points at
+    ///
+    /// References to transparent items are followed to their target. Returns `None`
+    /// for names not defined in the document, or that resolve to a built-in type.
+    pub fn kind_of(&self, name: &str) -> Option<TypeKind> {
+        let mut name = name;
+        // Bounded by the number of items, to stop on transparent cycles
+        for _ in 0..=self.items.len() {
+            match self.items.iter().find(|item| item.name() == name)? {
+                SchemaItem::Transparent(Transparent {
+                    target: CapnpType::UserDefined(target),
+                    ..
+                }) => name = target,
+                item => return item.kind(),
             }
         }
+        None
+    }
 
-        Ok(())
+    /// Sets whether references to types not defined in the document are accepted
+    pub fn set_allow_undefined_types(&mut self, allow: bool) {
+        self.allow_undefined_types = allow;
     }
 
-    /// Renders the struct as Cap'n Proto schema text
-    /// Automatically validates the struct before rendering
-    pub fn render(&self) -> Result<String, ValidationError> {
-        // Validate before rendering
-        self.validate()?;
+    /// Returns non-fatal problems found in the document's structs
+    pub fn validate_warnings(&self) -> Vec<ValidationWarning> {
+        self.validate_warnings_with(&RenderOptions::default())
+    }
 
-        let mut output = String::new();
+    /// Returns non-fatal problems found in the document's structs, including nested
+    /// ones, and those only reported when rendering with `options`
+    pub fn validate_warnings_with(&self, options: &RenderOptions) -> Vec<ValidationWarning> {
+        let mut structs = Vec::new();
+        collect_structs(&self.items, &mut structs);
 
-        writeln!(&mut output, "struct {} {{", self.name).unwrap();
+        let mut warnings: Vec<ValidationWarning> =
+            structs.iter().flat_map(|s| s.validate_warnings()).collect();
 
-        // Render regular fields
-        for field in &self.fields {
-            writeln!(&mut output, "  {}", field.render()).unwrap();
+        if options.warn_on_byte_lists {
+            for s in &structs {
+                warnings.extend(s.byte_list_warnings());
+            }
         }
 
-        // Render extra fields (for backwards compatibility)
-        for extra_field in &self.extra_fields {
-            writeln!(&mut output, "  {};", extra_field).unwrap();
+        if options.warn_on_reorder && self.field_order == FieldOrder::ById {
+            for s in &structs {
+                let groups = s.groups.iter().map(|group| &group.fields);
+                let fields: Vec<String> = core::iter::once(&s.fields)
+                    .chain(groups)
+                    .flat_map(|fields| reordered_fields(fields))
+                    .collect();
+                if !fields.is_empty() {
+                    warnings.push(ValidationWarning::FieldsReordered {
+                        type_name: s.name.clone(),
+                        fields,
+                    });
+                }
+            }
         }
 
-        // Render union if present
-        if let Some(union) = &self.union {
-            write!(&mut output, "{}", union.render()).unwrap();
+        warnings
+    }
+
+    /// Validates all structs in the document for ID conflicts, and checks that no
+    /// two items define the same type name
+    ///
+    /// Unless undefined types are allowed, also checks that every referenced type is
+    /// defined in the document or declared external.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        check_items(&self.items)?;
+
+        if let Some(item) = self
+            .items
+            .iter()
+            .find(|item| self.external_types.contains_key(item.name()))
+        {
+            return Err(ValidationError::DuplicateTypeName {
+                name: item.name().to_string(),
+            });
         }
 
-        writeln!(&mut output, "}}").unwrap();
+        if !self.allow_undefined_types
+            && let Some(err) = undefined_types(&self.items).into_iter().find(|err| {
+                !matches!(err, ValidationError::UndefinedType { name, .. }
+                    if self.external_types.contains_key(name))
+            })
+        {
+            return Err(err);
+        }
 
-        Ok(output)
+        Ok(())
     }
-}
 
-impl Field {
-    /// Creates a new field
-    pub fn new(name: String, id: u32, field_type: CapnpType) -> Self {
-        Self {
-            name,
-            id,
-            field_type,
+    /// Runs every check `capnpc` would apply to the rendered schema, returning all
+    /// problems found rather than just the first
+    ///
+    /// On top of [`Schema::validate`], this requires each struct's IDs to have no gaps
+    /// (removed fields can be kept as extra fields), type names to start with an
+    /// upper-case letter and other names with a lower-case one, and no name to contain
+    /// anything but ASCII letters and digits.
+    pub fn validate_capnp_compatible(&self) -> Vec<ValidationError> {
+        let mut errors = validate_items(&self.items).err().unwrap_or_default();
+        errors.retain(|err| {
+            !matches!(err, ValidationError::UndefinedType { name, .. }
+                if self.allow_undefined_types || self.external_types.contains_key(name))
+        });
+        for item in &self.items {
+            check_capnp_rules(item, "", &mut errors);
         }
+        errors
     }
 
-    /// Renders the field as Cap'n Proto schema text
-    pub fn render(&self) -> String {
-        format!("{} @{} :{};", self.name, self.id, self.field_type.render())
+    /// Adds all items from an iterator to the document
+    pub fn extend(&mut self, items: impl IntoIterator<Item = SchemaItem>) {
+        self.items.extend(items);
     }
-}
 
-impl Union {
-    /// Creates a new union
-    pub fn new() -> Self {
-        Self {
-            variants: Vec::new(),
-        }
+    /// Renders the document as Cap'n Proto schema text
+    /// Automatically validates all structs before rendering
+    ///
+    /// Top-level items are separated by one blank line, and non-empty output ends with
+    /// exactly one newline. Every rendering entry point follows the same rule.
+    pub fn render(&self) -> Result<String, ValidationError> {
+        self.render_with(&RenderOptions::default())
     }
 
-    /// Adds a variant to the union
-    pub fn add_variant(&mut self, variant: UnionVariant) {
-        self.variants.push(variant);
+    /// Renders the document as Cap'n Proto schema text with the given options
+    pub fn render_with(&self, options: &RenderOptions) -> Result<String, ValidationError> {
+        let items = render_items_ordered(&self.items, self.field_order, options)?;
+        Ok(self.with_preamble(items))
     }
 
-    /// Renders the union as Cap'n Proto schema text
-    pub fn render(&self) -> String {
-        let mut output = String::new();
+    /// Renders a normalized form of the document for snapshot tests and comparisons
+    ///
+    /// Items are sorted by name, fields and methods by ID, named groups by name and union
+    /// variants by their lowest ID, with the default layout. The document's field order
+    /// and any render options are ignored, so the output only changes when the schema
+    /// itself does, not when items or fields are added in a different order.
+    pub fn render_canonical(&self) -> Result<String, ValidationError> {
+        let mut items = self.items.clone();
+        items.sort_by(|a, b| a.name().cmp(b.name()));
+        for item in &mut items {
+            match item {
+                SchemaItem::Struct(s) => s.normalize(),
+                SchemaItem::Enum(e) => e.enumerants.sort_by_key(|enumerant| enumerant.id),
+                SchemaItem::Interface(i) => i.methods.sort_by_key(|method| method.id),
+                SchemaItem::Transparent(_) => {}
+            }
+        }
+        let items = render_items_ordered(&items, FieldOrder::ById, &RenderOptions::default())?;
+        Ok(self.with_preamble(items))
+    }
 
-        writeln!(&mut output, "  union {{").unwrap();
-        for variant in &self.variants {
-            writeln!(&mut output, "    {}", variant.render()).unwrap();
+    /// Prepends the document's file-level declarations to its rendered items: the
+    /// version comment, language annotations with their imports (once per language),
+    /// then external type imports
+    fn with_preamble(&self, items: String) -> String {
+        if self.version.is_none()
+            && self.language_annotations.is_empty()
+            && self.external_types.is_empty()
+        {
+            return items;
         }
-        writeln!(&mut output, "  }}").unwrap();
 
-        output
+        let mut output = String::new();
+        if let Some(version) = self.version {
+            writeln!(&mut output, "# schema version: {}", version).unwrap();
+        }
+        let mut imported = Vec::new();
+        for (kind, _) in &self.language_annotations {
+            if !imported.contains(kind) {
+                imported.push(*kind);
+                writeln!(&mut output, "{}", kind.import()).unwrap();
+            }
+        }
+        for (kind, value) in &self.language_annotations {
+            writeln!(&mut output, "{}({:?});", kind.application(), value).unwrap();
+        }
+        for (name, import_path) in &self.external_types {
+            writeln!(
+                &mut output,
+                "using {} = import {:?}.{};",
+                name, import_path, name
+            )
+            .unwrap();
+        }
+        if !items.is_empty() {
+            writeln!(&mut output).unwrap();
+        }
+        output + &items
     }
 }
 
-impl UnionVariant {
-    /// Creates a new union variant with a type
-    pub fn new(name: String, id: u32, variant_type: CapnpType) -> Self {
-        Self {
-            name,
-            variant_inner: UnionVariantInner::Type {
-                id,
-                capnp_type: variant_type,
-            },
+impl SchemaItem {
+    /// Returns the name of the type this item defines
+    pub fn name(&self) -> &str {
+        match self {
+            SchemaItem::Struct(s) => &s.name,
+            SchemaItem::Enum(e) => &e.name,
+            SchemaItem::Interface(i) => &i.name,
+            SchemaItem::Transparent(t) => &t.name,
         }
     }
 
-    /// Creates a new union variant with a group
-    pub fn new_group(name: String, fields: Vec<Field>) -> Self {
-        Self {
-            name,
-            variant_inner: UnionVariantInner::Group(fields),
+    /// Returns the kind of type this item defines, or `None` for transparent items,
+    /// which don't define a type of their own
+    pub fn kind(&self) -> Option<TypeKind> {
+        match self {
+            SchemaItem::Struct(s) if s.is_enum => Some(TypeKind::Enum),
+            SchemaItem::Struct(_) => Some(TypeKind::Struct),
+            SchemaItem::Enum(_) => Some(TypeKind::Enum),
+            SchemaItem::Interface(_) => Some(TypeKind::Interface),
+            SchemaItem::Transparent(_) => None,
         }
     }
 
-    /// Renders the variant as Cap'n Proto schema text
-    pub fn render(&self) -> String {
-        match &self.variant_inner {
-            UnionVariantInner::Type { capnp_type: ty, id } => {
-                format!("{} @{} :{};", self.name, id, ty.render())
-            }
-            UnionVariantInner::Group(fields) => {
-                let mut output = String::new();
-                output.push_str(&format!("{} :group {{\n", self.name));
-                for field in fields {
-                    output.push_str(&format!("      {}\n", field.render()));
-                }
-                output.push_str("    }");
-                output
+    /// Validates the item for ID conflicts
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        match self {
+            SchemaItem::Struct(s) => s.validate(),
+            SchemaItem::Enum(e) => e.validate(),
+            SchemaItem::Interface(i) => i.validate(),
+            SchemaItem::Transparent(_) => Ok(()),
+        }
+    }
+
+    /// Converts a struct holding only an anonymous union of `Void` variants, as derived
+    /// from a Rust enum with unit variants, into a native enum
+    ///
+    /// Returns the item unchanged as the error if it's anything else, including a
+    /// struct with fields, a type ID or generic parameters.
+    #[allow(clippy::result_large_err)]
+    pub fn try_into_native_enum(self) -> Result<SchemaItem, Self> {
+        let SchemaItem::Struct(s) = &self else {
+            return Err(self);
+        };
+        let [union] = &s.unions[..] else {
+            return Err(self);
+        };
+        let only_union = s.has_no_members_besides_unions()
+            && s.nested.is_empty()
+            && s.generic_params.is_empty()
+            && s.type_id.is_none()
+            && union.name.is_none()
+            && !union.variants.is_empty();
+        let enumerants: Option<Vec<Enumerant>> = union
+            .variants
+            .iter()
+            .map(|variant| match &variant.variant_inner {
+                UnionVariantInner::Type {
+                    id,
+                    capnp_type: CapnpType::Void,
+                } => Some(Enumerant {
+                    name: variant.name.clone(),
+                    id: *id,
+                }),
+                _ => None,
+            })
+            .collect();
+        match enumerants {
+            Some(enumerants) if only_union => Ok(SchemaItem::Enum(Enum {
+                name: s.name.clone(),
+                enumerants,
+            })),
+            _ => Err(self),
+        }
+    }
+
+    /// Collects the names of all user-defined types referenced by this item
+    fn collect_references(&self, references: &mut BTreeSet<String>) {
+        self.for_each_type(&mut |capnp_type| capnp_type.collect_references(references));
+    }
+
+    /// Collects the names of this item and of every type nested inside it
+    fn collect_names<'a>(&'a self, names: &mut BTreeSet<&'a str>) {
+        names.insert(self.name());
+        if let SchemaItem::Struct(s) = self {
+            for item in &s.nested {
+                item.collect_names(names);
             }
         }
     }
-}
 
-impl CapnpType {
-    /// Renders the type as Cap'n Proto schema text
-    pub fn render(&self) -> String {
+    /// Renames this item, or a type nested inside it, from `from` to `to`
+    fn rename_definition(&mut self, from: &str, to: &str) {
+        let name = match self {
+            SchemaItem::Struct(s) => {
+                for item in &mut s.nested {
+                    item.rename_definition(from, to);
+                }
+                &mut s.name
+            }
+            SchemaItem::Enum(e) => &mut e.name,
+            SchemaItem::Interface(i) => &mut i.name,
+            SchemaItem::Transparent(t) => &mut t.name,
+        };
+        if name == from {
+            *name = to.to_string();
+        }
+    }
+
+    /// Calls `f` on every type this item refers to
+    fn for_each_type(&self, f: &mut impl FnMut(&CapnpType)) {
         match self {
-            CapnpType::Bool => "Bool".to_string(),
-            CapnpType::Int8 => "Int8".to_string(),
-            CapnpType::Int16 => "Int16".to_string(),
-            CapnpType::Int32 => "Int32".to_string(),
-            CapnpType::Int64 => "Int64".to_string(),
-            CapnpType::UInt8 => "UInt8".to_string(),
-            CapnpType::UInt16 => "UInt16".to_string(),
-            CapnpType::UInt32 => "UInt32".to_string(),
-            CapnpType::UInt64 => "UInt64".to_string(),
-            CapnpType::Float32 => "Float32".to_string(),
-            CapnpType::Float64 => "Float64".to_string(),
-            CapnpType::Text => "Text".to_string(),
-            CapnpType::Void => "Void".to_string(),
-            CapnpType::List(inner) => format!("List({})", inner.render()),
-            CapnpType::UserDefined(name) => name.clone(),
+            SchemaItem::Struct(s) => s.for_each_type(f),
+            SchemaItem::Enum(_) => {}
+            SchemaItem::Interface(i) => i.for_each_type(f),
+            SchemaItem::Transparent(t) => f(&t.target),
         }
     }
-}
 
-impl Default for Schema {
-    fn default() -> Self {
-        Self::new()
+    /// Calls `f` on every type this item refers to, mutably
+    fn for_each_type_mut(&mut self, f: &mut impl FnMut(&mut CapnpType)) {
+        match self {
+            SchemaItem::Struct(s) => s.for_each_type_mut(f),
+            SchemaItem::Enum(_) => {}
+            SchemaItem::Interface(i) => i.for_each_type_mut(f),
+            SchemaItem::Transparent(t) => f(&mut t.target),
+        }
     }
-}
 
-impl Default for Union {
-    fn default() -> Self {
-        Self::new()
+    /// Renders the item as Cap'n Proto schema text
+    pub fn render(&self) -> Result<String, ValidationError> {
+        self.render_ordered(FieldOrder::default(), &RenderOptions::default())
+    }
+
+    fn render_ordered(
+        &self,
+        field_order: FieldOrder,
+        options: &RenderOptions,
+    ) -> Result<String, ValidationError> {
+        self.validate()?;
+
+        let mut output = String::new();
+        self.write_ordered(&mut output, field_order, options);
+        Ok(output)
+    }
+
+    /// Appends the item's schema text to `output` without validating it first
+    fn write_ordered(&self, output: &mut String, field_order: FieldOrder, options: &RenderOptions) {
+        match self {
+            SchemaItem::Struct(s) => s.write_ordered(output, field_order, options),
+            SchemaItem::Enum(e) => e.write_ordered(output, field_order, options),
+            SchemaItem::Interface(i) => i.write_ordered(output, field_order, options),
+            SchemaItem::Transparent(t) => {
+                write!(output, "using {} = ", t.name).unwrap();
+                t.target.write(output);
+                writeln!(output, ";").unwrap();
+            }
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Struct {
+    /// Creates a new struct with the given name
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            type_id: None,
+            generic_params: Vec::new(),
+            fields: Vec::new(),
+            groups: Vec::new(),
+            unions: Vec::new(),
+            extra_fields: Vec::new(),
+            raw_lines: Vec::new(),
+            nested: Vec::new(),
+            allow_empty: false,
+            is_enum: false,
+        }
+    }
+
+    /// Returns a type that references this struct by name, for use in other fields
+    pub fn type_ref(&self) -> CapnpType {
+        CapnpType::UserDefined(self.name.clone())
+    }
+
+    /// Pins the struct's Cap'n Proto type ID, which must have its high bit set
+    pub fn set_type_id(&mut self, type_id: u64) {
+        self.type_id = Some(type_id);
+    }
+
+    /// Adds a generic parameter to the struct, making it `struct Name(T, ...)`
+    pub fn add_generic_param(&mut self, param: String) {
+        self.generic_params.push(param);
+    }
+
+    /// Adds a field to the struct
+    pub fn add_field(&mut self, field: Field) {
+        self.fields.push(field);
+    }
+
+    /// Adds a named (non-union) group to the struct
+    pub fn add_group(&mut self, group: Group) {
+        self.groups.push(group);
+    }
+
+    /// Sets the union for this struct
+    pub fn set_union(&mut self, union: Union) {
+        self.unions = vec![union];
+    }
+
+    /// Adds another union to the struct; all but one must be named
+    pub fn add_union(&mut self, union: Union) {
+        self.unions.push(union);
+    }
+
+    /// Returns the struct's first union, for the common case of a single union
+    pub fn union(&self) -> Option<&Union> {
+        self.unions.first()
+    }
+
+    /// Adds an extra field (for backwards compatibility)
+    pub fn add_extra_field(&mut self, extra_field: String) {
+        self.extra_fields.push(extra_field);
+    }
+
+    /// Adds a line to copy verbatim into the struct body, for declarations the model
+    /// doesn't support; unlike extra fields, raw lines are not checked for IDs
+    pub fn add_raw_line(&mut self, line: String) {
+        self.raw_lines.push(line);
+    }
+
+    /// Marks the struct as intentionally empty, exempting it from
+    /// `RenderOptions::forbid_empty_structs`
+    pub fn set_allow_empty(&mut self, allow_empty: bool) {
+        self.allow_empty = allow_empty;
+    }
+
+    /// Marks the struct as the rendering of a Rust enum
+    pub fn set_is_enum(&mut self, is_enum: bool) {
+        self.is_enum = is_enum;
+    }
+
+    /// Whether the struct has nothing to render inside its braces besides nested types
+    fn has_no_members(&self) -> bool {
+        self.unions.is_empty() && self.has_no_members_besides_unions()
+    }
+
+    /// Whether the struct has nothing to render inside its braces besides nested types
+    /// and unions
+    fn has_no_members_besides_unions(&self) -> bool {
+        self.fields.is_empty()
+            && self.groups.is_empty()
+            && self.extra_fields.is_empty()
+            && self.raw_lines.is_empty()
+    }
+
+    /// Declares a type inside this struct, e.g. one only this struct uses
+    pub fn add_nested(&mut self, item: SchemaItem) {
+        self.nested.push(item);
+    }
+
+    /// Returns every field, group member and union group member
+    fn all_fields(&self) -> impl Iterator<Item = &Field> {
+        let group_fields = self.groups.iter().flat_map(|group| &group.fields);
+        let union_fields = self
+            .unions
+            .iter()
+            .flat_map(|union| &union.variants)
+            .flat_map(|variant| match &variant.variant_inner {
+                UnionVariantInner::Type { .. } => [].iter(),
+                UnionVariantInner::Group(fields) => fields.iter(),
+            });
+        self.fields.iter().chain(group_fields).chain(union_fields)
+    }
+
+    /// Calls `f` on the type of every field, group member and union variant, including
+    /// those of nested types
+    fn for_each_type(&self, f: &mut impl FnMut(&CapnpType)) {
+        for item in &self.nested {
+            item.for_each_type(f);
+        }
+        self.for_each_member_type(f);
+    }
+
+    /// Calls `f` on the type of every field, group member and union variant, excluding
+    /// those of nested types
+    fn for_each_member_type(&self, f: &mut impl FnMut(&CapnpType)) {
+        let fields = self.fields.iter();
+        let group_fields = self.groups.iter().flat_map(|group| &group.fields);
+        for field in fields.chain(group_fields) {
+            f(&field.field_type);
+        }
+        for union in &self.unions {
+            for variant in &union.variants {
+                match &variant.variant_inner {
+                    UnionVariantInner::Type { capnp_type, .. } => f(capnp_type),
+                    UnionVariantInner::Group(fields) => {
+                        for field in fields {
+                            f(&field.field_type);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Calls `f` on the type of every field, group member and union variant, mutably
+    fn for_each_type_mut(&mut self, f: &mut impl FnMut(&mut CapnpType)) {
+        for item in &mut self.nested {
+            item.for_each_type_mut(f);
+        }
+        self.for_each_member_type_mut(f);
+    }
+
+    /// Calls `f` on the type of every field, group member and union variant, excluding
+    /// those of nested types, mutably
+    fn for_each_member_type_mut(&mut self, f: &mut impl FnMut(&mut CapnpType)) {
+        let fields = self.fields.iter_mut();
+        let group_fields = self.groups.iter_mut().flat_map(|group| &mut group.fields);
+        for field in fields.chain(group_fields) {
+            f(&mut field.field_type);
+        }
+        for union in &mut self.unions {
+            for variant in &mut union.variants {
+                match &mut variant.variant_inner {
+                    UnionVariantInner::Type { capnp_type, .. } => f(capnp_type),
+                    UnionVariantInner::Group(fields) => {
+                        for field in fields {
+                            f(&mut field.field_type);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Validates that all IDs in the struct are unique and that field defaults fit
+    /// their types
+    /// This includes regular field IDs, group member IDs, union variant IDs, and union
+    /// group field IDs, which all share the struct's ID space. Structs referenced by
+    /// fields have ID spaces of their own, as in Cap'n Proto.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if let Some(id) = self.type_id.filter(|id| id & (1 << 63) == 0) {
+            return Err(ValidationError::InvalidTypeId {
+                name: self.name.clone(),
+                id,
+            });
+        }
+
+        for field in self.all_fields() {
+            field.validate().map_err(|err| match err {
+                ValidationError::InvalidDefault { field, reason, .. } => {
+                    ValidationError::InvalidDefault {
+                        type_name: self.name.clone(),
+                        field,
+                        reason,
+                    }
+                }
+                err => err,
+            })?;
+        }
+
+        for item in &self.nested {
+            item.validate()?;
+        }
+
+        if self
+            .unions
+            .iter()
+            .filter(|union| union.name.is_none())
+            .count()
+            > 1
+        {
+            return Err(ValidationError::MultipleAnonymousUnions {
+                type_name: self.name.clone(),
+            });
+        }
+
+        // Check for duplicates, reporting the lowest colliding ID
+        let id_locations = self.id_locations();
+        for (id, locations) in &id_locations {
+            if locations.len() > 1 {
+                return Err(ValidationError::DuplicateId {
+                    type_name: self.name.clone(),
+                    id: *id,
+                    locations: locations.clone(),
+                });
+            }
+        }
+
+        // Union members may interleave with other fields, but capnpc numbers the whole
+        // struct's ordinals from 0 without gaps, so every ordinal below a union's highest
+        // must be used by something
+        for union in &self.unions {
+            let Some(&highest) = union.ordinals().iter().max() else {
+                continue;
+            };
+            if let Some(missing) = (0..highest).find(|id| !id_locations.contains_key(id)) {
+                return Err(ValidationError::UnionOrdinalGap {
+                    type_name: self.name.clone(),
+                    union_name: union.name.clone(),
+                    missing,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns non-fatal problems with the struct, such as IDs far beyond the number
+    /// of IDs in use (likely typos like `@1000` for `@10`)
+    pub fn validate_warnings(&self) -> Vec<ValidationWarning> {
+        let id_locations = self.id_locations();
+        let limit = id_locations.len() as u64 + SUSPICIOUS_ID_GAP;
+
+        id_locations
+            .into_iter()
+            .filter(|(id, _)| u64::from(*id) > limit)
+            .flat_map(|(id, locations)| {
+                locations
+                    .into_iter()
+                    .map(move |location| ValidationWarning::SuspiciousId {
+                        type_name: self.name.clone(),
+                        id,
+                        location,
+                    })
+            })
+            .collect()
+    }
+
+    /// Returns a warning for every member whose type contains a `List(UInt8)`, which
+    /// should almost always be `Data`
+    fn byte_list_warnings(&self) -> Vec<ValidationWarning> {
+        self.typed_members()
+            .into_iter()
+            .filter(|(_, capnp_type)| capnp_type.contains_byte_list())
+            .map(|(location, _)| ValidationWarning::ByteList {
+                type_name: self.name.clone(),
+                location,
+            })
+            .collect()
+    }
+
+    /// Describes every field, group member and union variant with the type it holds
+    fn typed_members(&self) -> Vec<(String, &CapnpType)> {
+        let mut members = Vec::new();
+        for field in &self.fields {
+            members.push((format!("struct field '{}'", field.name), &field.field_type));
+        }
+        for group in &self.groups {
+            for field in &group.fields {
+                let location = format!("struct group '{}' field '{}'", group.name, field.name);
+                members.push((location, &field.field_type));
+            }
+        }
+        for union in &self.unions {
+            for variant in &union.variants {
+                match &variant.variant_inner {
+                    UnionVariantInner::Type { capnp_type, .. } => {
+                        members.push((format!("union variant '{}'", variant.name), capnp_type));
+                    }
+                    UnionVariantInner::Group(fields) => {
+                        for field in fields {
+                            let location =
+                                format!("union group '{}' field '{}'", variant.name, field.name);
+                            members.push((location, &field.field_type));
+                        }
+                    }
+                }
+            }
+        }
+        members
+    }
+
+    /// Looks up the field, group member or union variant using an ID
+    ///
+    /// If the ID is used more than once, the first use is returned, searching fields,
+    /// then groups, then the union.
+    pub fn field_by_id(&self, id: u32) -> Option<IdLocation<'_>> {
+        if let Some(field) = self.fields.iter().find(|field| field.id == id) {
+            return Some(IdLocation::Field(field));
+        }
+
+        for group in &self.groups {
+            if let Some(field) = group.fields.iter().find(|field| field.id == id) {
+                return Some(IdLocation::GroupMember { group, field });
+            }
+        }
+
+        let variant = self
+            .unions
+            .iter()
+            .find_map(|union| union.variant_by_ordinal(id))?;
+        match &variant.variant_inner {
+            UnionVariantInner::Type { .. } => Some(IdLocation::UnionVariant(variant)),
+            UnionVariantInner::Group(fields) => {
+                let field = fields.iter().find(|field| field.id == id)?;
+                Some(IdLocation::UnionGroupMember { variant, field })
+            }
+        }
+    }
+
+    /// Sorts nested types and named groups by name and union variants by their lowest
+    /// ID, for canonical rendering
+    fn normalize(&mut self) {
+        self.nested.sort_by(|a, b| a.name().cmp(b.name()));
+        for item in &mut self.nested {
+            match item {
+                SchemaItem::Struct(s) => s.normalize(),
+                SchemaItem::Enum(e) => e.enumerants.sort_by_key(|enumerant| enumerant.id),
+                SchemaItem::Interface(i) => i.methods.sort_by_key(|method| method.id),
+                SchemaItem::Transparent(_) => {}
+            }
+        }
+        self.groups.sort_by(|a, b| a.name.cmp(&b.name));
+        for union in &mut self.unions {
+            union
+                .variants
+                .sort_by_key(|variant| match &variant.variant_inner {
+                    UnionVariantInner::Type { id, .. } => Some(*id),
+                    UnionVariantInner::Group(fields) => fields.iter().map(|f| f.id).min(),
+                });
+        }
+    }
+
+    /// Lists every ID in the struct's ID space with a description of where it's used,
+    /// e.g. `(2, "union group 'circle' field 'radius'")`, sorted by ID
+    ///
+    /// These are the locations reported by `ValidationError::DuplicateId`; an ID used
+    /// more than once appears once per use.
+    pub fn id_usages(&self) -> Vec<(u32, String)> {
+        self.id_locations()
+            .into_iter()
+            .flat_map(|(id, locations)| locations.into_iter().map(move |location| (id, location)))
+            .collect()
+    }
+
+    /// Collects every ID in the struct's ID space, with the locations using it
+    fn id_locations(&self) -> BTreeMap<u32, Vec<String>> {
+        let mut id_locations: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+
+        // Collect regular field IDs
+        for field in &self.fields {
+            let location = format!("struct field '{}'", field.name);
+            id_locations.entry(field.id).or_default().push(location);
+        }
+
+        // Collect group member IDs, which share the struct's ID space
+        for group in &self.groups {
+            for field in &group.fields {
+                let location = format!("struct group '{}' field '{}'", group.name, field.name);
+                id_locations.entry(field.id).or_default().push(location);
+            }
+        }
+
+        // Collect extra field IDs, which must not reuse a live field's ID
+        for extra_field in &self.extra_fields {
+            if let Some((name, id)) = parse_extra_field(extra_field) {
+                let location = format!("extra field '{}'", name);
+                id_locations.entry(id).or_default().push(location);
+            }
+        }
+
+        // Collect union variant and union group field IDs
+        for union in &self.unions {
+            for variant in &union.variants {
+                match &variant.variant_inner {
+                    UnionVariantInner::Type { id: variant_id, .. } => {
+                        let location = format!("union variant '{}'", variant.name);
+                        id_locations.entry(*variant_id).or_default().push(location);
+                    }
+
+                    // If this variant is a group, collect its field IDs too
+                    UnionVariantInner::Group(fields) => {
+                        for field in fields {
+                            let location =
+                                format!("union group '{}' field '{}'", variant.name, field.name);
+                            id_locations.entry(field.id).or_default().push(location);
+                        }
+                    }
+                }
+            }
+        }
+
+        id_locations
+    }
+
+    /// Renders the struct as Cap'n Proto schema text
+    /// Automatically validates the struct before rendering
+    pub fn render(&self) -> Result<String, ValidationError> {
+        self.render_ordered(FieldOrder::default(), &RenderOptions::default())
+    }
+
+    fn render_ordered(
+        &self,
+        field_order: FieldOrder,
+        options: &RenderOptions,
+    ) -> Result<String, ValidationError> {
+        // Validate before rendering
+        self.validate()?;
+
+        let mut output = String::new();
+        self.write_ordered(&mut output, field_order, options);
+        Ok(output)
+    }
+
+    /// Appends the struct's schema text to `output` without validating it first
+    fn write_ordered(&self, output: &mut String, field_order: FieldOrder, options: &RenderOptions) {
+        write!(output, "struct {}", self.name).unwrap();
+        if !self.generic_params.is_empty() {
+            write!(output, "({})", self.generic_params.join(", ")).unwrap();
+        }
+        if let Some(type_id) = self.type_id {
+            write!(output, " @{:#x}", type_id).unwrap();
+        }
+        writeln!(output, " {{").unwrap();
+        self.render_body(output, field_order, options);
+        writeln!(output, "}}").unwrap();
+    }
+
+    /// Renders only the struct's indented fields, groups and union, without the
+    /// `struct Name {` / `}` wrapper
+    /// Automatically validates the struct before rendering
+    pub fn render_fields(&self) -> Result<String, ValidationError> {
+        self.validate()?;
+
+        let mut output = String::new();
+        self.render_body(
+            &mut output,
+            FieldOrder::default(),
+            &RenderOptions::default(),
+        );
+        Ok(output)
+    }
+
+    fn render_body(&self, output: &mut String, field_order: FieldOrder, options: &RenderOptions) {
+        let indent = &options.indent;
+
+        // Render nested types, indented one level; they're validated with their parent
+        for item in &self.nested {
+            let mut rendered = String::new();
+            item.write_ordered(&mut rendered, field_order, options);
+            for line in rendered.lines() {
+                if line.is_empty() {
+                    writeln!(output).unwrap();
+                } else {
+                    writeln!(output, "{}{}", indent, line).unwrap();
+                }
+            }
+        }
+
+        // Render regular fields
+        for field in order_fields(&self.fields, field_order) {
+            output.push_str(indent);
+            field.write_with(output, options);
+            output.push('\n');
+        }
+
+        // Render named groups
+        for group in &self.groups {
+            writeln!(output, "{}{} :group {{", indent, group.name).unwrap();
+            for field in order_fields(&group.fields, field_order) {
+                output.push_str(indent);
+                output.push_str(indent);
+                field.write_with(output, options);
+                output.push('\n');
+            }
+            writeln!(output, "{}}}", indent).unwrap();
+        }
+
+        // Render extra fields (for backwards compatibility)
+        for extra_field in &self.extra_fields {
+            writeln!(output, "{}{};", indent, extra_field).unwrap();
+        }
+
+        // Render unions
+        for union in &self.unions {
+            union.write_ordered(output, field_order, options, 1);
+        }
+
+        // Render raw lines as given
+        for line in &self.raw_lines {
+            writeln!(output, "{}{}", indent, line).unwrap();
+        }
+    }
+}
+
+impl Enum {
+    /// Creates a new enum with no enumerants
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            enumerants: Vec::new(),
+        }
+    }
+
+    /// Adds an enumerant to the enum
+    pub fn add_enumerant(&mut self, name: String, id: u32) {
+        self.enumerants.push(Enumerant { name, id });
+    }
+
+    /// Validates that all enumerant ordinals are unique
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut id_locations: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+        for enumerant in &self.enumerants {
+            let location = format!("enumerant '{}'", enumerant.name);
+            id_locations.entry(enumerant.id).or_default().push(location);
+        }
+
+        for (id, locations) in id_locations {
+            if locations.len() > 1 {
+                return Err(ValidationError::DuplicateId {
+                    type_name: self.name.clone(),
+                    id,
+                    locations,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Renders the enum as Cap'n Proto schema text
+    /// Automatically validates the enum before rendering
+    pub fn render(&self) -> Result<String, ValidationError> {
+        self.render_ordered(FieldOrder::default(), &RenderOptions::default())
+    }
+
+    fn render_ordered(
+        &self,
+        field_order: FieldOrder,
+        options: &RenderOptions,
+    ) -> Result<String, ValidationError> {
+        self.validate()?;
+
+        let mut output = String::new();
+        self.write_ordered(&mut output, field_order, options);
+        Ok(output)
+    }
+
+    /// Appends the enum's schema text to `output` without validating it first
+    fn write_ordered(&self, output: &mut String, field_order: FieldOrder, options: &RenderOptions) {
+        let mut enumerants: Vec<&Enumerant> = self.enumerants.iter().collect();
+        if field_order == FieldOrder::ById {
+            enumerants.sort_by_key(|enumerant| enumerant.id);
+        }
+
+        writeln!(output, "enum {} {{", self.name).unwrap();
+        for enumerant in enumerants {
+            writeln!(
+                output,
+                "{}{} @{};",
+                options.indent, enumerant.name, enumerant.id
+            )
+            .unwrap();
+        }
+        writeln!(output, "}}").unwrap();
+    }
+}
+
+impl Interface {
+    /// Creates a new interface with no methods
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            methods: Vec::new(),
+        }
+    }
+
+    /// Adds a method to the interface
+    pub fn add_method(&mut self, method: Method) {
+        self.methods.push(method);
+    }
+
+    /// Calls `f` on the type of every method parameter and result
+    fn for_each_type(&self, f: &mut impl FnMut(&CapnpType)) {
+        for method in &self.methods {
+            for param in method.params.iter().chain(&method.results) {
+                f(&param.param_type);
+            }
+        }
+    }
+
+    /// Calls `f` on the type of every method parameter and result, mutably
+    fn for_each_type_mut(&mut self, f: &mut impl FnMut(&mut CapnpType)) {
+        for method in &mut self.methods {
+            for param in method.params.iter_mut().chain(&mut method.results) {
+                f(&mut param.param_type);
+            }
+        }
+    }
+
+    /// Validates that all method IDs in the interface are unique
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut id_locations: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+
+        for method in &self.methods {
+            let location = format!("interface method '{}'", method.name);
+            id_locations.entry(method.id).or_default().push(location);
+        }
+
+        for (id, locations) in id_locations {
+            if locations.len() > 1 {
+                return Err(ValidationError::DuplicateId {
+                    type_name: self.name.clone(),
+                    id,
+                    locations,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Renders the interface as Cap'n Proto schema text
+    /// Automatically validates the interface before rendering
+    pub fn render(&self) -> Result<String, ValidationError> {
+        self.render_ordered(FieldOrder::default(), &RenderOptions::default())
+    }
+
+    fn render_ordered(
+        &self,
+        field_order: FieldOrder,
+        options: &RenderOptions,
+    ) -> Result<String, ValidationError> {
+        self.validate()?;
+
+        let mut output = String::new();
+        self.write_ordered(&mut output, field_order, options);
+        Ok(output)
+    }
+
+    /// Appends the interface's schema text to `output` without validating it first
+    fn write_ordered(&self, output: &mut String, field_order: FieldOrder, options: &RenderOptions) {
+        let mut methods: Vec<&Method> = self.methods.iter().collect();
+        if field_order == FieldOrder::ById {
+            methods.sort_by_key(|method| method.id);
+        }
+
+        writeln!(output, "interface {} {{", self.name).unwrap();
+        for method in methods {
+            output.push_str(&options.indent);
+            method.write(output);
+            output.push('\n');
+        }
+        writeln!(output, "}}").unwrap();
+    }
+}
+
+impl Method {
+    /// Creates a new method with no parameters or results
+    pub fn new(name: String, id: u32) -> Self {
+        Self {
+            name,
+            id,
+            params: Vec::new(),
+            results: Vec::new(),
+        }
+    }
+
+    /// Adds a parameter to the method
+    pub fn add_param(&mut self, name: String, param_type: CapnpType) {
+        self.params.push(Param { name, param_type });
+    }
+
+    /// Adds a result to the method
+    pub fn add_result(&mut self, name: String, result_type: CapnpType) {
+        self.results.push(Param {
+            name,
+            param_type: result_type,
+        });
+    }
+
+    /// Renders the method as Cap'n Proto schema text
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        self.write(&mut output);
+        output
+    }
+
+    fn write(&self, output: &mut String) {
+        write!(output, "{} @{} ", self.name, self.id).unwrap();
+        write_params(output, &self.params);
+        output.push_str(" -> ");
+        write_params(output, &self.results);
+        output.push(';');
+    }
+}
+
+/// Appends a method's parameter or result list, e.g. `(expr :Expression)`
+fn write_params(output: &mut String, params: &[Param]) {
+    output.push('(');
+    for (i, param) in params.iter().enumerate() {
+        if i > 0 {
+            output.push_str(", ");
+        }
+        write!(output, "{} :", param.name).unwrap();
+        param.param_type.write(output);
+    }
+    output.push(')');
+}
+
+impl Field {
+    /// Creates a new field
+    pub fn new(name: String, id: u32, field_type: CapnpType) -> Self {
+        Self {
+            name,
+            id,
+            field_type,
+            default: None,
+            rust_name: None,
+            deprecated: false,
+            time_unit: None,
+        }
+    }
+
+    /// Marks the field as deprecated; it still renders and keeps its ID
+    pub fn set_deprecated(&mut self, deprecated: bool) {
+        self.deprecated = deprecated;
+    }
+
+    /// Records the unit a time value is counted in
+    pub fn set_time_unit(&mut self, time_unit: String) {
+        self.time_unit = Some(time_unit);
+    }
+
+    /// Records the Rust field name this field was generated from
+    pub fn set_rust_name(&mut self, rust_name: String) {
+        self.rust_name = Some(rust_name);
+    }
+
+    /// Sets the field's default value
+    pub fn set_default(&mut self, default: DefaultValue) {
+        self.default = Some(default);
+    }
+
+    /// Validates that the default value, if any, fits the field's type
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let Some(default) = &self.default else {
+            return Ok(());
+        };
+
+        let fits = match (default, &self.field_type) {
+            (DefaultValue::Int(value), CapnpType::Int8) => i8::try_from(*value).is_ok(),
+            (DefaultValue::Int(value), CapnpType::Int16) => i16::try_from(*value).is_ok(),
+            (DefaultValue::Int(value), CapnpType::Int32) => i32::try_from(*value).is_ok(),
+            (DefaultValue::Int(_), CapnpType::Int64) => true,
+            (DefaultValue::Int(value), CapnpType::UInt8) => u8::try_from(*value).is_ok(),
+            (DefaultValue::Int(value), CapnpType::UInt16) => u16::try_from(*value).is_ok(),
+            (DefaultValue::Int(value), CapnpType::UInt32) => u32::try_from(*value).is_ok(),
+            (DefaultValue::Int(value), CapnpType::UInt64) => *value >= 0,
+            (DefaultValue::UInt(_), CapnpType::UInt64) => true,
+            (
+                DefaultValue::UInt(_),
+                CapnpType::Int8
+                | CapnpType::Int16
+                | CapnpType::Int32
+                | CapnpType::Int64
+                | CapnpType::UInt8
+                | CapnpType::UInt16
+                | CapnpType::UInt32,
+            ) => false,
+            (
+                DefaultValue::Int(_) | DefaultValue::UInt(_) | DefaultValue::Float(_),
+                CapnpType::Float32 | CapnpType::Float64,
+            ) => true,
+            (DefaultValue::Bool(_), CapnpType::Bool) => true,
+            (DefaultValue::Text(_), CapnpType::Text) => true,
+            _ => {
+                return Err(ValidationError::InvalidDefault {
+                    type_name: String::new(),
+                    field: self.name.clone(),
+                    reason: format!(
+                        "{} is not a valid default for {}",
+                        default.render(),
+                        self.field_type.render()
+                    ),
+                });
+            }
+        };
+
+        if fits {
+            Ok(())
+        } else {
+            Err(ValidationError::InvalidDefault {
+                type_name: String::new(),
+                field: self.name.clone(),
+                reason: format!(
+                    "{} is out of range for {}",
+                    default.render(),
+                    self.field_type.render()
+                ),
+            })
+        }
+    }
+
+    /// Renders the field as Cap'n Proto schema text
+    pub fn render(&self) -> String {
+        self.render_with(&RenderOptions::default())
+    }
+
+    fn render_with(&self, options: &RenderOptions) -> String {
+        let mut output = String::new();
+        self.write_with(&mut output, options);
+        output
+    }
+
+    /// Appends the field's declaration, followed by its comment if it has one
+    fn write_with(&self, output: &mut String, options: &RenderOptions) {
+        write!(output, "{} @{} :", self.name, self.id).unwrap();
+        self.field_type.write(output);
+        if let Some(default) = &self.default {
+            write!(output, " = {}", default.render()).unwrap();
+        }
+        output.push(';');
+
+        let rust_name = self
+            .rust_name
+            .as_ref()
+            .filter(|_| options.rust_name_comments);
+        let mut comments = Vec::new();
+        if self.deprecated {
+            comments.push("deprecated".to_string());
+        }
+        if let Some(time_unit) = &self.time_unit {
+            comments.push(time_unit.clone());
+        }
+        if let Some(rust_name) = rust_name {
+            comments.push(format!("rust: {}", rust_name));
+        }
+        if !comments.is_empty() {
+            write!(output, "  # {}", comments.join("; ")).unwrap();
+        }
+    }
+}
+
+impl DefaultValue {
+    /// Renders the value as a Cap'n Proto literal
+    pub fn render(&self) -> String {
+        match self {
+            DefaultValue::Int(value) => value.to_string(),
+            DefaultValue::UInt(value) => value.to_string(),
+            DefaultValue::Float(value) if value.is_nan() => "nan".to_string(),
+            DefaultValue::Float(value) => value.to_string(),
+            DefaultValue::Bool(value) => value.to_string(),
+            DefaultValue::Text(value) => {
+                let mut output = String::from("\"");
+                for c in value.chars() {
+                    match c {
+                        '"' => output.push_str("\\\""),
+                        '\\' => output.push_str("\\\\"),
+                        '\n' => output.push_str("\\n"),
+                        '\r' => output.push_str("\\r"),
+                        '\t' => output.push_str("\\t"),
+                        c => output.push(c),
+                    }
+                }
+                output.push('"');
+                output
+            }
+        }
+    }
+}
+
+impl Group {
+    /// Creates a new group with the given fields
+    pub fn new(name: String, fields: Vec<Field>) -> Self {
+        Self { name, fields }
+    }
+}
+
+impl Union {
+    /// Creates a new union
+    pub fn new() -> Self {
+        Self {
+            name: None,
+            variants: Vec::new(),
+        }
+    }
+
+    /// Names the union, making it a named union field of the enclosing struct
+    pub fn set_name(&mut self, name: String) {
+        self.name = Some(name);
+    }
+
+    /// Adds a variant to the union
+    pub fn add_variant(&mut self, variant: UnionVariant) {
+        self.variants.push(variant);
+    }
+
+    /// The ordinals of the union's members: the IDs of type variants and of group
+    /// variants' fields
+    fn ordinals(&self) -> Vec<u32> {
+        self.variants
+            .iter()
+            .flat_map(|variant| match &variant.variant_inner {
+                UnionVariantInner::Type { id, .. } => vec![*id],
+                UnionVariantInner::Group(fields) => fields.iter().map(|field| field.id).collect(),
+            })
+            .collect()
+    }
+
+    /// Looks up the variant using an ID, either as its own ordinal or, for group
+    /// variants, as the ID of one of its members
+    pub fn variant_by_ordinal(&self, id: u32) -> Option<&UnionVariant> {
+        self.variants
+            .iter()
+            .find(|variant| match &variant.variant_inner {
+                UnionVariantInner::Type { id: variant_id, .. } => *variant_id == id,
+                UnionVariantInner::Group(fields) => fields.iter().any(|field| field.id == id),
+            })
+    }
+
+    /// Renders the union as Cap'n Proto schema text, indented as the direct member
+    /// of a top-level struct
+    pub fn render(&self) -> String {
+        self.render_at_depth(1)
+    }
+
+    /// Renders the union as Cap'n Proto schema text, indented `depth` levels
+    pub fn render_at_depth(&self, depth: usize) -> String {
+        let mut output = String::new();
+        self.write_ordered(
+            &mut output,
+            FieldOrder::default(),
+            &RenderOptions::default(),
+            depth,
+        );
+        output
+    }
+
+    fn write_ordered(
+        &self,
+        output: &mut String,
+        field_order: FieldOrder,
+        options: &RenderOptions,
+        depth: usize,
+    ) {
+        let indent = options.indent.repeat(depth);
+
+        match &self.name {
+            Some(name) => writeln!(output, "{}{} :union {{", indent, name).unwrap(),
+            None => writeln!(output, "{}union {{", indent).unwrap(),
+        }
+        for (i, variant) in self.variants.iter().enumerate() {
+            if options.blank_lines_between_groups
+                && i > 0
+                && variant.is_group()
+                && self.variants[i - 1].is_group()
+            {
+                writeln!(output).unwrap();
+            }
+            output.push_str(&indent);
+            output.push_str(&options.indent);
+            variant.write_ordered(output, field_order, options, depth + 1);
+            output.push('\n');
+        }
+        writeln!(output, "{}}}", indent).unwrap();
+    }
+}
+
+impl UnionVariant {
+    /// Creates a new union variant with a type
+    pub fn new(name: String, id: u32, variant_type: CapnpType) -> Self {
+        Self {
+            name,
+            variant_inner: UnionVariantInner::Type {
+                id,
+                capnp_type: variant_type,
+            },
+        }
+    }
+
+    /// Creates a new union variant with a group
+    pub fn new_group(name: String, fields: Vec<Field>) -> Self {
+        Self {
+            name,
+            variant_inner: UnionVariantInner::Group(fields),
+        }
+    }
+
+    fn is_group(&self) -> bool {
+        matches!(self.variant_inner, UnionVariantInner::Group(_))
+    }
+
+    /// Renders the variant as Cap'n Proto schema text, with a group's members and
+    /// closing brace indented for a union inside a top-level struct
+    ///
+    /// The first line is never indented, since the union indents it.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        self.write_ordered(
+            &mut output,
+            FieldOrder::default(),
+            &RenderOptions::default(),
+            2,
+        );
+        output
+    }
+
+    /// Appends the variant with its first line at nesting level `depth`
+    fn write_ordered(
+        &self,
+        output: &mut String,
+        field_order: FieldOrder,
+        options: &RenderOptions,
+        depth: usize,
+    ) {
+        match &self.variant_inner {
+            UnionVariantInner::Type { capnp_type: ty, id } => {
+                write!(output, "{} @{} :", self.name, id).unwrap();
+                ty.write(output);
+                output.push(';');
+            }
+            UnionVariantInner::Group(fields) => {
+                let member_indent = options.indent.repeat(depth + 1);
+                writeln!(output, "{} :group {{", self.name).unwrap();
+                for field in order_fields(fields, field_order) {
+                    output.push_str(&member_indent);
+                    field.write_with(output, options);
+                    output.push('\n');
+                }
+                output.push_str(&options.indent.repeat(depth));
+                output.push('}');
+            }
+        }
+    }
+}
+
+impl CapnpType {
+    /// Parses a type as written in schema text: a built-in type, `List(...)` of a
+    /// parseable type, or a user type name such as `Person` or `Outer.Inner`
+    pub fn parse(text: &str) -> Option<CapnpType> {
+        let text = text.trim();
+        let parsed = match text {
+            "Bool" => CapnpType::Bool,
+            "Int8" => CapnpType::Int8,
+            "Int16" => CapnpType::Int16,
+            "Int32" => CapnpType::Int32,
+            "Int64" => CapnpType::Int64,
+            "UInt8" => CapnpType::UInt8,
+            "UInt16" => CapnpType::UInt16,
+            "UInt32" => CapnpType::UInt32,
+            "UInt64" => CapnpType::UInt64,
+            "Float32" => CapnpType::Float32,
+            "Float64" => CapnpType::Float64,
+            "Text" => CapnpType::Text,
+            "Data" => CapnpType::Data,
+            "Void" => CapnpType::Void,
+            _ => {
+                if let Some(inner) = text
+                    .strip_prefix("List(")
+                    .and_then(|rest| rest.strip_suffix(')'))
+                {
+                    return Some(CapnpType::List(Box::new(CapnpType::parse(inner)?)));
+                }
+                let is_type_name = |segment: &str| {
+                    segment.starts_with(|c: char| c.is_ascii_uppercase())
+                        && segment.chars().all(|c| c.is_ascii_alphanumeric())
+                };
+                if !text.split('.').all(is_type_name) {
+                    return None;
+                }
+                CapnpType::UserDefined(text.to_string())
+            }
+        };
+        Some(parsed)
+    }
+
+    /// Replaces references to transparent types with their targets, following chains
+    /// of transparent types up to the number of transparent types (to stop on cycles)
+    fn resolve_transparent(&mut self, targets: &BTreeMap<&str, &CapnpType>, depth: usize) {
+        match self {
+            CapnpType::List(inner) | CapnpType::Option(inner) => {
+                inner.resolve_transparent(targets, depth)
+            }
+            CapnpType::Result(ok, err) => {
+                ok.resolve_transparent(targets, depth);
+                err.resolve_transparent(targets, depth);
+            }
+            CapnpType::UserDefined(name) if depth < targets.len() => {
+                if let Some(target) = targets.get(name.as_str()) {
+                    *self = (*target).clone();
+                    self.resolve_transparent(targets, depth + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether this type is or contains a `List(UInt8)`
+    fn contains_byte_list(&self) -> bool {
+        match self {
+            CapnpType::List(inner) if **inner == CapnpType::UInt8 => true,
+            CapnpType::List(inner) | CapnpType::Option(inner) => inner.contains_byte_list(),
+            CapnpType::Result(ok, err) => ok.contains_byte_list() || err.contains_byte_list(),
+            _ => false,
+        }
+    }
+
+    /// Collects the names of user-defined types referenced by this type
+    fn collect_references(&self, references: &mut BTreeSet<String>) {
+        match self {
+            CapnpType::List(inner) | CapnpType::Option(inner) => {
+                inner.collect_references(references)
+            }
+            CapnpType::Result(ok, err) => {
+                ok.collect_references(references);
+                err.collect_references(references);
+            }
+            CapnpType::UserDefined(name) => {
+                references.insert(name.clone());
+            }
+            _ => {}
+        }
+    }
+
+    /// Renames references to the user-defined type `from`, returning how many were
+    /// renamed
+    fn rename(&mut self, from: &str, to: &str) -> usize {
+        match self {
+            CapnpType::List(inner) | CapnpType::Option(inner) => inner.rename(from, to),
+            CapnpType::Result(ok, err) => ok.rename(from, to) + err.rename(from, to),
+            CapnpType::UserDefined(name) if name == from => {
+                *name = to.to_string();
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    /// Replaces the user-defined names inside `Option` and `Result` types with
+    /// `qualify(name)`, leaving other references alone
+    fn qualify_wrapped(&mut self, qualify: &impl Fn(&str) -> String, wrapped: bool) {
+        match self {
+            CapnpType::List(inner) => inner.qualify_wrapped(qualify, wrapped),
+            CapnpType::Option(inner) => inner.qualify_wrapped(qualify, true),
+            CapnpType::Result(ok, err) => {
+                ok.qualify_wrapped(qualify, true);
+                err.qualify_wrapped(qualify, true);
+            }
+            CapnpType::UserDefined(name) if wrapped => *name = qualify(name),
+            _ => {}
+        }
+    }
+
+    /// Collects the helper struct for every `Option` and `Result` in this type, keyed
+    /// by name
+    fn collect_helpers(&self, helpers: &mut BTreeMap<String, Struct>) {
+        match self {
+            CapnpType::List(inner) => inner.collect_helpers(helpers),
+            CapnpType::Option(inner) => {
+                helpers.entry(self.render()).or_insert_with(|| {
+                    union_helper(self.render(), ("none", &CapnpType::Void), ("some", inner))
+                });
+                inner.collect_helpers(helpers);
+            }
+            CapnpType::Result(ok, err) => {
+                helpers
+                    .entry(self.render())
+                    .or_insert_with(|| union_helper(self.render(), ("ok", ok), ("err", err)));
+                ok.collect_helpers(helpers);
+                err.collect_helpers(helpers);
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the type as an identifier fragment, used to name generated helper
+    /// structs (e.g. `ListText` in `OptionListText`)
+    fn name_fragment(&self) -> String {
+        match self {
+            CapnpType::List(inner) => format!("List{}", inner.name_fragment()),
+            _ => self.render().replace("::", "").replace('.', ""),
+        }
+    }
+
+    /// Renders the type as Cap'n Proto schema text
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        self.write(&mut output);
+        output
+    }
+
+    /// Appends the type as it appears in schema text
+    fn write(&self, output: &mut String) {
+        match self {
+            CapnpType::Bool => output.push_str("Bool"),
+            CapnpType::Int8 => output.push_str("Int8"),
+            CapnpType::Int16 => output.push_str("Int16"),
+            CapnpType::Int32 => output.push_str("Int32"),
+            CapnpType::Int64 => output.push_str("Int64"),
+            CapnpType::UInt8 => output.push_str("UInt8"),
+            CapnpType::UInt16 => output.push_str("UInt16"),
+            CapnpType::UInt32 => output.push_str("UInt32"),
+            CapnpType::UInt64 => output.push_str("UInt64"),
+            CapnpType::Float32 => output.push_str("Float32"),
+            CapnpType::Float64 => output.push_str("Float64"),
+            CapnpType::Text => output.push_str("Text"),
+            CapnpType::Data => output.push_str("Data"),
+            CapnpType::Void => output.push_str("Void"),
+            CapnpType::List(inner) => {
+                output.push_str("List(");
+                inner.write(output);
+                output.push(')');
+            }
+            CapnpType::Option(inner) => write!(output, "Option{}", inner.name_fragment()).unwrap(),
+            CapnpType::Result(ok, err) => write!(
+                output,
+                "Result{}{}",
+                ok.name_fragment(),
+                err.name_fragment()
+            )
+            .unwrap(),
+            CapnpType::UserDefined(name) => output.push_str(name),
+            CapnpType::GenericParam(name) => output.push_str(name),
+        }
+    }
+
+    /// Returns the Rust type that the derive maps to this type, e.g. `Vec<String>` for
+    /// `List(Text)`, for generating Rust code from a schema
+    ///
+    /// User-defined types and generic parameters keep their name.
+    pub fn suggested_rust_type(&self) -> String {
+        match self {
+            CapnpType::Bool => "bool".to_string(),
+            CapnpType::Int8 => "i8".to_string(),
+            CapnpType::Int16 => "i16".to_string(),
+            CapnpType::Int32 => "i32".to_string(),
+            CapnpType::Int64 => "i64".to_string(),
+            CapnpType::UInt8 => "u8".to_string(),
+            CapnpType::UInt16 => "u16".to_string(),
+            CapnpType::UInt32 => "u32".to_string(),
+            CapnpType::UInt64 => "u64".to_string(),
+            CapnpType::Float32 => "f32".to_string(),
+            CapnpType::Float64 => "f64".to_string(),
+            CapnpType::Text => "String".to_string(),
+            CapnpType::Data => "Vec<u8>".to_string(),
+            CapnpType::Void => "()".to_string(),
+            CapnpType::List(inner) => format!("Vec<{}>", inner.suggested_rust_type()),
+            CapnpType::Option(inner) => format!("Option<{}>", inner.suggested_rust_type()),
+            CapnpType::Result(ok, err) => format!(
+                "Result<{}, {}>",
+                ok.suggested_rust_type(),
+                err.suggested_rust_type()
+            ),
+            CapnpType::UserDefined(name) => name.clone(),
+            CapnpType::GenericParam(name) => name.clone(),
+        }
+    }
+}
+
+/// Renders a slice of items as Cap'n Proto schema text without building a [`Schema`]
+///
+/// The output is identical to rendering a default [`Schema`] containing the same items.
+pub fn render_items(items: &[SchemaItem]) -> Result<String, ValidationError> {
+    render_items_ordered(items, FieldOrder::default(), &RenderOptions::default())
+}
+
+/// Validates a slice of items without building a [`Schema`] or rendering them,
+/// reporting every problem found rather than just the first
+///
+/// This runs the same checks as [`Schema::validate`]: IDs and defaults within each
+/// item, duplicate type names, and references to types not in `items`.
+pub fn validate_items(items: &[SchemaItem]) -> Result<(), Vec<ValidationError>> {
+    let mut errors = Vec::new();
+
+    let mut names = BTreeSet::new();
+    for item in items {
+        if !names.insert(item.name()) {
+            errors.push(ValidationError::DuplicateTypeName {
+                name: item.name().to_string(),
+            });
+        }
+        if let Err(err) = item.validate() {
+            errors.push(err);
+        }
+    }
+    errors.extend(undefined_types(items));
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Checks items for the problems that prevent rendering, returning the first one
+fn check_items(items: &[SchemaItem]) -> Result<(), ValidationError> {
+    let mut names = BTreeSet::new();
+    for item in items {
+        if !names.insert(item.name()) {
+            return Err(ValidationError::DuplicateTypeName {
+                name: item.name().to_string(),
+            });
+        }
+        item.validate()?;
+    }
+    Ok(())
+}
+
+/// Adds the errors `capnpc` would report for an item, and any types nested inside it,
+/// that `SchemaItem::validate` doesn't catch
+fn check_capnp_rules(item: &SchemaItem, parent: &str, errors: &mut Vec<ValidationError>) {
+    let mut check_name = |type_name: &str, name: &str, is_type: bool| {
+        if let Err(reason) = check_capnp_name(name, is_type) {
+            errors.push(ValidationError::InvalidName {
+                type_name: type_name.to_string(),
+                name: name.to_string(),
+                reason: reason.to_string(),
+            });
+        }
+    };
+    check_name(parent, item.name(), true);
+
+    match item {
+        SchemaItem::Struct(s) => {
+            for param in &s.generic_params {
+                check_name(&s.name, param, true);
+            }
+            for group in &s.groups {
+                check_name(&s.name, &group.name, false);
+            }
+            for union in &s.unions {
+                if let Some(name) = &union.name {
+                    check_name(&s.name, name, false);
+                }
+                for variant in &union.variants {
+                    check_name(&s.name, &variant.name, false);
+                }
+            }
+            for field in s.all_fields() {
+                check_name(&s.name, &field.name, false);
+            }
+
+            // Only look for gaps once IDs are otherwise valid, so a union's gap isn't
+            // reported twice
+            if s.validate().is_ok() {
+                let id_locations = s.id_locations();
+                if let Some(missing) =
+                    (0..id_locations.len() as u32).find(|id| !id_locations.contains_key(id))
+                {
+                    errors.push(ValidationError::IdGap {
+                        type_name: s.name.clone(),
+                        missing,
+                    });
+                }
+            }
+
+            for nested in &s.nested {
+                check_capnp_rules(nested, &s.name, errors);
+            }
+        }
+        SchemaItem::Enum(e) => {
+            for enumerant in &e.enumerants {
+                check_name(&e.name, &enumerant.name, false);
+            }
+        }
+        SchemaItem::Interface(i) => {
+            for method in &i.methods {
+                check_name(&i.name, &method.name, false);
+                for param in method.params.iter().chain(&method.results) {
+                    check_name(&i.name, &param.name, false);
+                }
+            }
+        }
+        SchemaItem::Transparent(_) => {}
+    }
+}
+
+/// Checks a name against `capnpc`'s rules: letters and digits only, starting with an
+/// upper-case letter for types and a lower-case one for everything else
+fn check_capnp_name(name: &str, is_type: bool) -> Result<(), &'static str> {
+    let Some(first) = name.chars().next() else {
+        return Err("names can't be empty");
+    };
+    if !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err("names may only contain ASCII letters and digits");
+    }
+    if is_type && !first.is_ascii_uppercase() {
+        return Err("type names must start with an upper-case letter");
+    }
+    if !is_type && !first.is_ascii_lowercase() {
+        return Err("names other than type names must start with a lower-case letter");
+    }
+    Ok(())
+}
+
+/// Checks that every struct, including nested ones, has members or allows being empty
+fn check_not_empty(items: &[SchemaItem]) -> Result<(), ValidationError> {
+    for item in items {
+        if let SchemaItem::Struct(s) = item {
+            if s.has_no_members() && !s.allow_empty {
+                return Err(ValidationError::EmptyStruct {
+                    name: s.name.clone(),
+                });
+            }
+            check_not_empty(&s.nested)?;
+        }
+    }
+    Ok(())
+}
+
+/// Collects the structs in `items`, followed by the structs nested inside each of them
+fn collect_structs<'a>(items: &'a [SchemaItem], structs: &mut Vec<&'a Struct>) {
+    for item in items {
+        if let SchemaItem::Struct(s) = item {
+            structs.push(s);
+            collect_structs(&s.nested, structs);
+        }
+    }
+}
+
+/// Returns an error for every reference to a type not defined in `items`
+///
+/// Names resolve like they do in capnpc: a type nested inside a struct is only in
+/// scope inside that struct, and is named `Parent.Child` elsewhere.
+fn undefined_types(items: &[SchemaItem]) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    let mut reported = BTreeSet::new();
+    for item in items {
+        let mut references = BTreeSet::new();
+        collect_unresolved(items, item, &mut Vec::new(), &mut references);
+        for name in references {
+            reported.insert(name.clone());
+            errors.push(ValidationError::UndefinedType {
+                name,
+                referenced_by: item.name().to_string(),
+            });
+        }
+    }
+
+    // Helper structs are rendered at file scope, so what they reference must resolve
+    // there too
+    for (helper_name, helper) in collect_item_helpers(&qualify_helper_references(items)) {
+        let mut references = BTreeSet::new();
+        helper.for_each_member_type(&mut |t| t.collect_references(&mut references));
+        for name in references {
+            if !resolves(&name, &[], items) && !reported.contains(&name) {
+                errors.push(ValidationError::UndefinedType {
+                    name,
+                    referenced_by: helper_name.clone(),
+                });
+            }
+        }
+    }
+    errors
+}
+
+/// Collects the references made by `item`, and the types nested inside it, that don't
+/// resolve from the enclosing structs in `scopes`
+fn collect_unresolved<'a>(
+    items: &'a [SchemaItem],
+    item: &'a SchemaItem,
+    scopes: &mut Vec<&'a Struct>,
+    unresolved: &mut BTreeSet<String>,
+) {
+    let mut references = BTreeSet::new();
+    if let SchemaItem::Struct(s) = item {
+        scopes.push(s);
+        s.for_each_member_type(&mut |t| t.collect_references(&mut references));
+        for nested in &s.nested {
+            collect_unresolved(items, nested, scopes, unresolved);
+        }
+    } else {
+        item.collect_references(&mut references);
+    }
+    for name in references {
+        if !resolves(&name, scopes, items) {
+            unresolved.insert(name);
+        }
+    }
+    if let SchemaItem::Struct(_) = item {
+        scopes.pop();
+    }
+}
+
+/// Returns the helper structs the items' `Option` and `Result` types need, keyed by
+/// name
+fn collect_item_helpers(items: &[SchemaItem]) -> BTreeMap<String, Struct> {
+    let mut helpers = BTreeMap::new();
+    for item in items {
+        match item {
+            SchemaItem::Struct(s) => s.for_each_type(&mut |t| t.collect_helpers(&mut helpers)),
+            SchemaItem::Enum(_) => {}
+            SchemaItem::Interface(i) => i.for_each_type(&mut |t| t.collect_helpers(&mut helpers)),
+            SchemaItem::Transparent(t) => t.target.collect_helpers(&mut helpers),
+        }
+    }
+    helpers
+}
+
+/// Qualifies references to nested types inside `Option` and `Result` types, e.g.
+/// `Address` inside `Person` becomes `Person.Address`
+///
+/// Helper structs are generated at file scope, where a nested type is only reachable by
+/// its qualified name; this also names the helper `OptionPersonAddress`, so helpers for
+/// same-named types nested in different parents don't clash.
+fn qualify_helper_references(items: &[SchemaItem]) -> Vec<SchemaItem> {
+    let mut qualified = items.to_vec();
+    for item in &mut qualified {
+        if let SchemaItem::Struct(s) = item {
+            let path = vec![s.name.clone()];
+            qualify_struct_helper_references(items, s, path);
+        }
+    }
+    qualified
+}
+
+/// Qualifies the helper references of `s`, found at `path` in `items`, and of the
+/// structs nested inside it
+fn qualify_struct_helper_references(items: &[SchemaItem], s: &mut Struct, path: Vec<String>) {
+    let qualify = |name: &str| {
+        let first = name.split('.').next().unwrap_or_default();
+        // Find the innermost enclosing struct that declares the first segment
+        for depth in (1..=path.len()).rev() {
+            let Some(scope) = struct_at(items, &path[..depth]) else {
+                continue;
+            };
+            if scope.nested.iter().any(|item| item.name() == first) {
+                return format!("{}.{}", path[..depth].join("."), name);
+            }
+        }
+        name.to_string()
+    };
+    s.for_each_member_type_mut(&mut |t| t.qualify_wrapped(&qualify, false));
+
+    for nested in &mut s.nested {
+        if let SchemaItem::Struct(child) = nested {
+            let mut child_path = path.clone();
+            child_path.push(child.name.clone());
+            qualify_struct_helper_references(items, child, child_path);
+        }
+    }
+}
+
+/// Returns the struct named by `path`, a top-level struct followed by nested ones
+fn struct_at<'a>(items: &'a [SchemaItem], path: &[String]) -> Option<&'a Struct> {
+    let (first, rest) = path.split_first()?;
+    let mut current = match items.iter().find(|item| item.name() == first)? {
+        SchemaItem::Struct(s) => s,
+        _ => return None,
+    };
+    for name in rest {
+        current = match current.nested.iter().find(|item| item.name() == name)? {
+            SchemaItem::Struct(s) => s,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+/// Whether a type name like `Child` or `Parent.Child`, referenced from inside the
+/// `scopes` structs (outermost first), names a type in `items`
+fn resolves(name: &str, scopes: &[&Struct], items: &[SchemaItem]) -> bool {
+    let mut segments = name.split('.');
+    let first = segments.next().unwrap_or_default();
+    let start = scopes
+        .iter()
+        .rev()
+        .find_map(|scope| scope.nested.iter().find(|item| item.name() == first))
+        .or_else(|| items.iter().find(|item| item.name() == first));
+    let Some(mut current) = start else {
+        return false;
+    };
+    for segment in segments {
+        let SchemaItem::Struct(s) = current else {
+            return false;
+        };
+        match s.nested.iter().find(|item| item.name() == segment) {
+            Some(item) => current = item,
+            None => return false,
+        }
+    }
+    true
+}
+
+fn render_items_ordered(
+    items: &[SchemaItem],
+    field_order: FieldOrder,
+    options: &RenderOptions,
+) -> Result<String, ValidationError> {
+    // Validate everything before rendering anything
+    check_items(items)?;
+    if options.forbid_empty_structs {
+        check_not_empty(items)?;
+    }
+
+    // Transparent items are inlined into the types that reference them, and nested
+    // types wrapped in Option or Result are qualified for their file-scope helpers,
+    // both of which require a modified copy of the items
+    let resolved;
+    let items = if items
+        .iter()
+        .any(|item| matches!(item, SchemaItem::Transparent(_)))
+    {
+        resolved = resolve_transparent_items(items);
+        &resolved
+    } else {
+        items
+    };
+    let qualified;
+    let items = if items
+        .iter()
+        .any(|item| matches!(item, SchemaItem::Struct(s) if !s.nested.is_empty()))
+    {
+        qualified = qualify_helper_references(items);
+        &qualified
+    } else {
+        items
+    };
+
+    // Option and Result types refer to helper structs, which are generated once per
+    // type after the items themselves, sorted by name so the output doesn't depend on
+    // the order fields were added in
+    let helpers = collect_item_helpers(items);
+    if let Some(name) = helpers
+        .keys()
+        .find(|name| items.iter().any(|item| item.name() == name.as_str()))
+    {
+        return Err(ValidationError::DuplicateTypeName { name: name.clone() });
+    }
+
+    // Everything was validated above, and helpers are valid by construction, so the
+    // items are written straight into one buffer
+    let mut output = String::with_capacity(estimated_len(items) + helpers.len() * 64);
+
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            writeln!(&mut output).unwrap();
+        }
+        item.write_ordered(&mut output, field_order, options);
+    }
+    for helper in helpers.values() {
+        if !output.is_empty() {
+            writeln!(&mut output).unwrap();
+        }
+        helper.write_ordered(&mut output, field_order, options);
+    }
+
+    Ok(output)
+}
+
+/// Roughly estimates the length of the items' schema text, to size the output buffer
+fn estimated_len(items: &[SchemaItem]) -> usize {
+    let mut members = 0;
+    for item in items {
+        item.for_each_type(&mut |_| members += 1);
+    }
+    items.len() * 32 + members * 32
+}
+
+/// Reads the name and ID of an extra field like `oldUserId @1 :UInt64`
+///
+/// Returns `None` if the text doesn't start with a name and an `@N` ID.
+fn parse_extra_field(extra_field: &str) -> Option<(&str, u32)> {
+    let (name, rest) = extra_field.split_once('@')?;
+    let name = name.trim();
+    let digits_end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let id = rest[..digits_end].parse().ok()?;
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((name, id))
+}
+
+/// Builds a helper struct holding a two-variant union with IDs 0 and 1
+fn union_helper(name: String, first: (&str, &CapnpType), second: (&str, &CapnpType)) -> Struct {
+    let mut union = Union::new();
+    union.add_variant(UnionVariant::new(first.0.to_string(), 0, first.1.clone()));
+    union.add_variant(UnionVariant::new(second.0.to_string(), 1, second.1.clone()));
+    let mut helper = Struct::new(name);
+    helper.set_union(union);
+    helper
+}
+
+/// Replaces references to transparent items with their targets and drops the
+/// transparent items themselves
+fn resolve_transparent_items(items: &[SchemaItem]) -> Vec<SchemaItem> {
+    let targets: BTreeMap<&str, &CapnpType> = items
+        .iter()
+        .filter_map(|item| match item {
+            SchemaItem::Transparent(t) => Some((t.name.as_str(), &t.target)),
+            _ => None,
+        })
+        .collect();
+
+    items
+        .iter()
+        .filter_map(|item| match item {
+            SchemaItem::Struct(s) => {
+                let mut s = s.clone();
+                s.for_each_type_mut(&mut |capnp_type| capnp_type.resolve_transparent(&targets, 0));
+                Some(SchemaItem::Struct(s))
+            }
+            SchemaItem::Enum(e) => Some(SchemaItem::Enum(e.clone())),
+            SchemaItem::Interface(i) => {
+                let mut i = i.clone();
+                i.for_each_type_mut(&mut |capnp_type| capnp_type.resolve_transparent(&targets, 0));
+                Some(SchemaItem::Interface(i))
+            }
+            SchemaItem::Transparent(_) => None,
+        })
+        .collect()
+}
+
+/// Returns the names of fields whose position changes when sorted by ID, in
+/// declaration order
+fn reordered_fields(fields: &[Field]) -> Vec<String> {
+    let ordered = order_fields(fields, FieldOrder::ById);
+    fields
+        .iter()
+        .zip(ordered)
+        .filter(|(declared, ordered)| declared.id != ordered.id)
+        .map(|(declared, _)| declared.name.clone())
+        .collect()
+}
+
+/// Returns the fields in the order they should be rendered
+fn order_fields(fields: &[Field], field_order: FieldOrder) -> Vec<&Field> {
+    let mut ordered: Vec<&Field> = fields.iter().collect();
+    if field_order == FieldOrder::ById {
+        ordered.sort_by_key(|field| field.id);
+    }
+    ordered
+}
+
+/// Computes a deterministic Cap'n Proto file ID for a schema
+///
+/// The ID is derived from the sorted names of the schema's top-level types, so it
+/// stays the same as fields are added or removed. The high bit is always set, as
+/// Cap'n Proto requires.
+pub fn stable_file_id(schema: &Schema) -> u64 {
+    let mut names: Vec<&str> = schema.items.iter().map(SchemaItem::name).collect();
+    names.sort_unstable();
+
+    // 64-bit FNV-1a, which unlike std's hashers is guaranteed stable across releases
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for name in names {
+        for byte in name.bytes().chain(core::iter::once(b'\n')) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    hash | (1 << 63)
+}
+
+/// Formats a file ID as the `@0x...;` header line of a schema file (without a newline)
+pub fn format_file_id(id: u64) -> String {
+    format!("@0x{:x};", id)
+}
+
+/// Checks that a file ID has its high bit set, as Cap'n Proto requires
+pub fn check_file_id(id: u64) -> Result<(), ValidationError> {
+    if id & (1 << 63) == 0 {
+        return Err(ValidationError::InvalidFileId { id });
+    }
+    Ok(())
+}
+
+/// Sets the high bit of an ID, turning e.g. a small test ID into a valid file or type ID
+pub fn with_high_bit(id: u64) -> u64 {
+    id | (1 << 63)
+}
+
+/// Parses a file ID from a `@0x...;` header line, also accepting the bare `0x...` form
+///
+/// Surrounding whitespace is ignored. Returns `None` for anything else, including IDs
+/// without the `0x` prefix.
+pub fn parse_file_id(header: &str) -> Option<u64> {
+    let header = header.trim();
+    let header = header.strip_prefix('@').unwrap_or(header);
+    let header = header.strip_suffix(';').unwrap_or(header);
+    let digits = header.strip_prefix("0x")?;
+    if !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(digits, 16).ok()
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            indent: "  ".to_string(),
+            blank_lines_between_groups: false,
+            rust_name_comments: false,
+            forbid_empty_structs: false,
+            warn_on_reorder: false,
+            warn_on_byte_lists: false,
+        }
+    }
+}
+
+impl Default for Union {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Document tests
+    #[test]
+    fn test_empty_document() {
+        let doc = Schema::new();
+        assert_eq!(doc.items.len(), 0);
+        assert_eq!(doc.render().unwrap(), "");
+    }
+
+    #[test]
+    fn test_document_default() {
+        let doc = Schema::default();
+        assert_eq!(doc.items.len(), 0);
+    }
+
+    #[test]
+    fn test_document_with_struct() {
+        let s = Struct::new("Test".to_string());
+        let doc = Schema::with_struct(s);
+
+        assert_eq!(doc.items.len(), 1);
+        assert!(matches!(doc.items[0], SchemaItem::Struct(_)));
+    }
+
+    #[test]
+    fn test_document_add_item() {
+        let mut doc = Schema::new();
+        let s = Struct::new("Test".to_string());
+
+        doc.add_item(SchemaItem::Struct(s));
+        assert_eq!(doc.items.len(), 1);
+    }
+
+    #[test]
+    fn test_document_len_and_structs() {
+        let mut doc = Schema::new();
+        assert!(doc.is_empty());
+        assert_eq!(doc.len(), 0);
+
+        doc.extend(vec![
+            SchemaItem::Struct(Struct::new("A".to_string())),
+            SchemaItem::Interface(Interface::new("Service".to_string())),
+            SchemaItem::Transparent(Transparent {
+                name: "UserId".to_string(),
+                target: CapnpType::UInt64,
+            }),
+            SchemaItem::Struct(Struct::new("B".to_string())),
+        ]);
+
+        assert!(!doc.is_empty());
+        assert_eq!(doc.len(), 4);
+        let names: Vec<&str> = doc.structs().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["A", "B"]);
+    }
+
+    #[test]
+    fn test_document_extend() {
+        let mut doc = Schema::new();
+        doc.extend(vec![
+            SchemaItem::Struct(Struct::new("A".to_string())),
+            SchemaItem::Struct(Struct::new("B".to_string())),
+        ]);
+
+        assert_eq!(doc.items.len(), 2);
+        assert_eq!(doc.render().unwrap(), render_items(&doc.items).unwrap());
+    }
+
+    #[test]
+    fn test_struct_type_id() {
+        let mut s = Struct::new("Person".to_string());
+        s.set_type_id(0xd4c9b59b3b3e6c7a);
+        s.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+
+        assert_eq!(
+            s.render().unwrap(),
+            "struct Person @0xd4c9b59b3b3e6c7a {\n  name @0 :Text;\n}\n"
+        );
+
+        s.set_type_id(0x14c9b59b3b3e6c7a);
+        assert!(matches!(
+            s.validate(),
+            Err(ValidationError::InvalidTypeId { .. })
+        ));
+    }
+
+    #[test]
+    fn test_interface() {
+        let mut evaluate = Method::new("evaluate".to_string(), 0);
+        evaluate.add_param(
+            "expr".to_string(),
+            CapnpType::UserDefined("Expression".to_string()),
+        );
+        evaluate.add_result("value".to_string(), CapnpType::Float64);
+
+        let mut calculator = Interface::new("Calculator".to_string());
+        calculator.add_method(evaluate);
+
+        assert_eq!(
+            calculator.render().unwrap(),
+            "interface Calculator {\n  evaluate @0 (expr :Expression) -> (value :Float64);\n}\n"
+        );
+
+        let mut doc = Schema::new();
+        doc.add_item(SchemaItem::Interface(calculator));
+        assert_eq!(doc.kind_of("Calculator"), Some(TypeKind::Interface));
+    }
+
+    #[test]
+    fn test_interface_duplicate_method_ids() {
+        let mut interface = Interface::new("Service".to_string());
+        interface.add_method(Method::new("start".to_string(), 0));
+        interface.add_method(Method::new("stop".to_string(), 0));
+
+        let Err(ValidationError::DuplicateId { id, locations, .. }) = interface.validate() else {
+            panic!("Expected DuplicateId error");
+        };
+        assert_eq!(id, 0);
+        assert!(locations.contains(&"interface method 'start'".to_string()));
+        assert!(locations.contains(&"interface method 'stop'".to_string()));
+    }
+
+    #[test]
+    fn test_referenced_types() {
+        let mut company = Struct::new("Company".to_string());
+        company.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        company.add_field(Field::new(
+            "employees".to_string(),
+            1,
+            CapnpType::List(Box::new(CapnpType::List(Box::new(CapnpType::UserDefined(
+                "Person".to_string(),
+            ))))),
+        ));
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new_group(
+            "public".to_string(),
+            vec![Field::new(
+                "exchange".to_string(),
+                2,
+                CapnpType::UserDefined("Exchange".to_string()),
+            )],
+        ));
+        company.set_union(union);
+
+        let references = Schema::with_struct(company).referenced_types();
+
+        assert!(references.contains("Person"));
+        assert!(references.contains("Exchange"));
+        assert!(!references.contains("Company"));
+        assert_eq!(references.len(), 2);
+    }
+
+    #[test]
+    fn test_referenced_struct_ids_are_scoped_separately() {
+        let mut person = Struct::new("Person".to_string());
+        person.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        person.add_group(Group::new(
+            "address".to_string(),
+            vec![Field::new("city".to_string(), 1, CapnpType::Text)],
+        ));
+
+        let mut company = Struct::new("Company".to_string());
+        company.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        company.add_field(Field::new(
+            "ceo".to_string(),
+            1,
+            CapnpType::UserDefined("Person".to_string()),
+        ));
+        company.add_field(Field::new(
+            "employees".to_string(),
+            2,
+            CapnpType::List(Box::new(CapnpType::UserDefined("Person".to_string()))),
+        ));
+
+        let mut doc = Schema::new();
+        doc.add_item(SchemaItem::Struct(company));
+        doc.add_item(SchemaItem::Struct(person));
+
+        assert_eq!(doc.validate(), Ok(()));
+        assert!(doc.render().is_ok());
+    }
+
+    #[test]
+    fn test_byte_list_warning() {
+        let bytes = CapnpType::List(Box::new(CapnpType::UInt8));
+        let mut s = Struct::new("Blob".to_string());
+        s.add_field(Field::new("data".to_string(), 0, CapnpType::Data));
+        s.add_field(Field::new("raw".to_string(), 1, bytes.clone()));
+        s.add_field(Field::new(
+            "chunks".to_string(),
+            2,
+            CapnpType::List(Box::new(bytes.clone())),
+        ));
+        let mut doc = Schema::with_struct(s);
+        let options = RenderOptions {
+            warn_on_byte_lists: true,
+            ..RenderOptions::default()
+        };
+
+        assert!(doc.validate_warnings().is_empty());
+        let warnings = doc.validate_warnings_with(&options);
+        assert_eq!(
+            warnings,
+            vec![
+                ValidationWarning::ByteList {
+                    type_name: "Blob".to_string(),
+                    location: "struct field 'raw'".to_string(),
+                },
+                ValidationWarning::ByteList {
+                    type_name: "Blob".to_string(),
+                    location: "struct field 'chunks'".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "The type of struct field 'raw' in 'Blob' contains List(UInt8); use Data for bytes"
+        );
+
+        // Nested structs are checked too
+        let mut header = Struct::new("Header".to_string());
+        header.add_field(Field::new("magic".to_string(), 0, bytes));
+        let mut file = Struct::new("File".to_string());
+        file.add_nested(SchemaItem::Struct(header));
+        doc = Schema::with_struct(file);
+        assert_eq!(
+            doc.validate_warnings_with(&options),
+            vec![ValidationWarning::ByteList {
+                type_name: "Header".to_string(),
+                location: "struct field 'magic'".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_warn_on_reorder() {
+        let mut s = Struct::new("Person".to_string());
+        s.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        s.add_field(Field::new("email".to_string(), 2, CapnpType::Text));
+        s.add_field(Field::new("age".to_string(), 1, CapnpType::UInt8));
+        s.add_field(Field::new("active".to_string(), 3, CapnpType::Bool));
+        let mut doc = Schema::with_struct(s);
+        let options = RenderOptions {
+            warn_on_reorder: true,
+            ..RenderOptions::default()
+        };
+
+        assert!(doc.validate_warnings().is_empty());
+        let warnings = doc.validate_warnings_with(&options);
+        assert_eq!(
+            warnings,
+            vec![ValidationWarning::FieldsReordered {
+                type_name: "Person".to_string(),
+                fields: vec!["email".to_string(), "age".to_string()],
+            }]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "Fields email, age of 'Person' are declared out of ID order and render in a \
+             different position"
+        );
+
+        // Nothing moves when rendering in source order
+        doc.set_field_order(FieldOrder::SourceOrder);
+        assert!(doc.validate_warnings_with(&options).is_empty());
+    }
+
+    #[test]
+    fn test_suspicious_id_warning() {
+        let mut s = Struct::new("Config".to_string());
+        s.add_field(Field::new("a".to_string(), 0, CapnpType::UInt32));
+        s.add_field(Field::new("b".to_string(), 1, CapnpType::UInt32));
+        s.add_field(Field::new("c".to_string(), 1000, CapnpType::UInt32));
+
+        assert_eq!(s.validate(), Ok(()));
+        assert_eq!(
+            Schema::with_struct(s).validate_warnings(),
+            vec![ValidationWarning::SuspiciousId {
+                type_name: "Config".to_string(),
+                id: 1000,
+                location: "struct field 'c'".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_validate_items_reports_all_errors() {
+        let mut invalid = Struct::new("Person".to_string());
+        invalid.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        invalid.add_field(Field::new("email".to_string(), 0, CapnpType::Text));
+        let items = [
+            SchemaItem::Struct(Struct::new("Person".to_string())),
+            SchemaItem::Struct(invalid),
+        ];
+
+        let errors = validate_items(&items).unwrap_err();
+
+        assert_eq!(errors.len(), 2);
+        assert!(errors.contains(&ValidationError::DuplicateTypeName {
+            name: "Person".to_string()
+        }));
+        assert!(
+            errors
+                .iter()
+                .any(|err| matches!(err, ValidationError::DuplicateId { id: 0, .. }))
+        );
+        assert_eq!(validate_items(&items[..1]), Ok(()));
+    }
+
+    #[test]
+    fn test_undefined_type_reference() {
+        let mut s = Struct::new("Order".to_string());
+        s.add_field(Field::new(
+            "item".to_string(),
+            0,
+            CapnpType::List(Box::new(CapnpType::UserDefined("Missing".to_string()))),
+        ));
+        let mut doc = Schema::with_struct(s);
+
+        assert_eq!(
+            doc.validate(),
+            Err(ValidationError::UndefinedType {
+                name: "Missing".to_string(),
+                referenced_by: "Order".to_string(),
+            })
+        );
+
+        doc.set_allow_undefined_types(true);
+        assert_eq!(doc.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_external_types() {
+        let mut s = Struct::new("Order".to_string());
+        s.add_field(Field::new(
+            "customer".to_string(),
+            0,
+            CapnpType::UserDefined("Customer".to_string()),
+        ));
+        let mut doc = Schema::with_struct(s);
+        doc.declare_external("Customer", "crm.capnp");
+
+        assert_eq!(doc.validate(), Ok(()));
+        assert!(doc.referenced_types().is_empty());
+        assert_eq!(
+            doc.render().unwrap(),
+            "using Customer = import \"crm.capnp\".Customer;\n\n\
+             struct Order {\n  customer @0 :Customer;\n}\n"
+        );
+
+        doc.declare_external("Order", "orders.capnp");
+        assert_eq!(
+            doc.validate(),
+            Err(ValidationError::DuplicateTypeName {
+                name: "Order".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_partition() {
+        let mut company = Struct::new("Company".to_string());
+        company.add_field(Field::new(
+            "employees".to_string(),
+            0,
+            CapnpType::List(Box::new(CapnpType::UserDefined("Person".to_string()))),
+        ));
+        let mut person = Struct::new("Person".to_string());
+        person.add_field(Field::new(
+            "status".to_string(),
+            0,
+            CapnpType::UserDefined("Status".to_string()),
+        ));
+        let mut status = Union::new();
+        status.add_variant(UnionVariant::new("active".to_string(), 0, CapnpType::Void));
+        let mut status_enum = Struct::new("Status".to_string());
+        status_enum.set_union(status);
+
+        company.add_field(Field::new(
+            "hq".to_string(),
+            1,
+            CapnpType::UserDefined("Location".to_string()),
+        ));
+
+        let mut doc = Schema::new();
+        doc.add_item(SchemaItem::Struct(person));
+        doc.add_item(SchemaItem::Struct(company));
+        doc.add_item(SchemaItem::Struct(status_enum));
+        doc.declare_external("Location", "geo.capnp");
+
+        let files = doc.partition(|item| match item.name() {
+            "Company" => "company.capnp".to_string(),
+            _ => "people.capnp".to_string(),
+        });
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["company.capnp", "people.capnp"]);
+
+        let (_, company_file) = &files[0];
+        assert_eq!(company_file.validate(), Ok(()));
+        assert_eq!(
+            company_file.render().unwrap(),
+            "using Location = import \"geo.capnp\".Location;\n\
+             using Person = import \"people.capnp\".Person;\n\n\
+             struct Company {\n  employees @0 :List(Person);\n  hq @1 :Location;\n}\n"
+        );
+
+        let (_, people_file) = &files[1];
+        assert_eq!(people_file.validate(), Ok(()));
+        assert!(people_file.external_types.is_empty());
+        assert_eq!(people_file.len(), 2);
+    }
+
+    #[test]
+    fn test_validate_capnp_compatible() {
+        let mut profile = Struct::new("Profile".to_string());
+        profile.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        profile.add_field(Field::new("email".to_string(), 2, CapnpType::Text));
+        let mut doc = Schema::with_struct(profile.clone());
+        assert_eq!(doc.validate(), Ok(()));
+        assert_eq!(
+            doc.validate_capnp_compatible(),
+            vec![ValidationError::IdGap {
+                type_name: "Profile".to_string(),
+                missing: 1,
+            }]
+        );
+
+        // A removed field kept as an extra field closes the gap
+        profile.add_extra_field("phone @1 :Text".to_string());
+        let doc_with_extra = Schema::with_struct(profile);
+        assert!(doc_with_extra.validate_capnp_compatible().is_empty());
+
+        let mut bad_names = Struct::new("settings".to_string());
+        bad_names.add_field(Field::new("dark_mode".to_string(), 0, CapnpType::Bool));
+        bad_names.add_field(Field::new(
+            "Owner".to_string(),
+            1,
+            CapnpType::UserDefined("Missing".to_string()),
+        ));
+        doc.add_item(SchemaItem::Struct(bad_names));
+        let errors: Vec<String> = doc
+            .validate_capnp_compatible()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "Type 'Missing' referenced by 'settings' is not defined",
+                "In 'Profile': ID 1 is unused; keep removed fields as extra fields so IDs have \
+                 no gaps",
+                "Invalid name 'settings': type names must start with an upper-case letter",
+                "In 'settings': Invalid name 'dark_mode': names may only contain ASCII letters \
+                 and digits",
+                "In 'settings': Invalid name 'Owner': names other than type names must start \
+                 with a lower-case letter",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kind_of_references() {
+        let mut doc = Schema::new();
+        doc.add_item(SchemaItem::Struct(Struct::new("Person".to_string())));
+        doc.add_item(SchemaItem::Transparent(Transparent {
+            name: "Owner".to_string(),
+            target: CapnpType::UserDefined("Person".to_string()),
+        }));
+        doc.add_item(SchemaItem::Transparent(Transparent {
+            name: "UserId".to_string(),
+            target: CapnpType::UInt64,
+        }));
+
+        assert_eq!(doc.kind_of("Person"), Some(TypeKind::Struct));
+        assert_eq!(doc.kind_of("Owner"), Some(TypeKind::Struct));
+        assert_eq!(doc.kind_of("UserId"), None);
+        assert_eq!(doc.kind_of("Missing"), None);
+    }
+
+    #[test]
+    fn test_multiple_structs_with_spacing() {
+        let mut doc = Schema::new();
+
+        let s1 = Struct::new("Person".to_string());
+        let s2 = Struct::new("Company".to_string());
+
+        doc.add_item(SchemaItem::Struct(s1));
+        doc.add_item(SchemaItem::Struct(s2));
+
+        let output = doc.render().unwrap();
+
+        assert!(output.contains("struct Person {"));
+        assert!(output.contains("struct Company {"));
+        // Test that there's spacing between structs
+        assert!(output.contains("}\n\nstruct Company"));
+    }
+
+    #[test]
+    fn test_render_fields_without_wrapper() {
+        let mut s = Struct::new("Point".to_string());
+        s.add_field(Field::new("x".to_string(), 0, CapnpType::Float32));
+        s.add_field(Field::new("y".to_string(), 1, CapnpType::Float32));
+
+        let fields = s.render_fields().unwrap();
+        assert_eq!(fields, "  x @0 :Float32;\n  y @1 :Float32;\n");
+        assert_eq!(
+            s.render().unwrap(),
+            format!("struct Point {{\n{}}}\n", fields)
+        );
+
+        s.add_field(Field::new("z".to_string(), 1, CapnpType::Float32));
+        assert!(s.render_fields().is_err());
+    }
+
+    #[test]
+    fn test_render_with_four_space_indent() {
+        let mut s = Struct::new("Shape".to_string());
+        s.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        s.add_group(Group::new(
+            "origin".to_string(),
+            vec![Field::new("x".to_string(), 1, CapnpType::Float32)],
+        ));
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new("empty".to_string(), 2, CapnpType::Void));
+        union.add_variant(UnionVariant::new_group(
+            "circle".to_string(),
+            vec![Field::new("radius".to_string(), 3, CapnpType::Float32)],
+        ));
+        s.set_union(union);
+
+        let options = RenderOptions {
+            indent: "    ".to_string(),
+            ..RenderOptions::default()
+        };
+        let output = Schema::with_struct(s).render_with(&options).unwrap();
+
+        assert_eq!(
+            output,
+            "struct Shape {\n    name @0 :Text;\n    origin :group {\n        x @1 :Float32;\n    }\n    \
+             union {\n        empty @2 :Void;\n        circle :group {\n            radius @3 :Float32;\n        \
+             }\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_rust_name_comments() {
+        let mut s = Struct::new("Person".to_string());
+        let mut full_name = Field::new("fullName".to_string(), 0, CapnpType::Text);
+        full_name.set_rust_name("full_name".to_string());
+        s.add_field(full_name);
+        s.add_field(Field::new("age".to_string(), 1, CapnpType::UInt8));
+        let doc = Schema::with_struct(s);
+
+        assert_eq!(
+            doc.render().unwrap(),
+            "struct Person {\n  fullName @0 :Text;\n  age @1 :UInt8;\n}\n"
+        );
+
+        let options = RenderOptions {
+            rust_name_comments: true,
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            doc.render_with(&options).unwrap(),
+            "struct Person {\n  fullName @0 :Text;  # rust: full_name\n  age @1 :UInt8;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_time_unit_comment() {
+        let mut s = Struct::new("Session".to_string());
+        let mut timeout = Field::new("timeout".to_string(), 0, CapnpType::UInt64);
+        timeout.set_time_unit("millis".to_string());
+        s.add_field(timeout);
+        let mut ttl = Field::new("ttl".to_string(), 1, CapnpType::UInt64);
+        ttl.set_time_unit("secs".to_string());
+        ttl.set_deprecated(true);
+        ttl.set_rust_name("ttl_secs".to_string());
+        s.add_field(ttl);
+
+        let options = RenderOptions {
+            rust_name_comments: true,
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            Schema::with_struct(s).render_with(&options).unwrap(),
+            "struct Session {\n  timeout @0 :UInt64;  # millis\n  ttl @1 :UInt64;  # deprecated; secs; rust: ttl_secs\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_deprecated_field() {
+        let mut s = Struct::new("Account".to_string());
+        let mut legacy_id = Field::new("legacyId".to_string(), 0, CapnpType::UInt64);
+        legacy_id.set_deprecated(true);
+        legacy_id.set_rust_name("legacy_id".to_string());
+        s.add_field(legacy_id);
+        s.add_field(Field::new("id".to_string(), 1, CapnpType::Text));
+
+        let doc = Schema::with_struct(s.clone());
+        assert_eq!(
+            doc.render().unwrap(),
+            "struct Account {\n  legacyId @0 :UInt64;  # deprecated\n  id @1 :Text;\n}\n"
+        );
+        let options = RenderOptions {
+            rust_name_comments: true,
+            ..RenderOptions::default()
+        };
+        assert!(
+            doc.render_with(&options)
+                .unwrap()
+                .contains("legacyId @0 :UInt64;  # deprecated; rust: legacy_id\n")
+        );
+
+        // The ID stays reserved
+        s.add_field(Field::new("owner".to_string(), 0, CapnpType::Text));
+        assert!(matches!(
+            s.validate(),
+            Err(ValidationError::DuplicateId { id: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_blank_lines_between_union_groups() {
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new("empty".to_string(), 0, CapnpType::Void));
+        union.add_variant(UnionVariant::new("unknown".to_string(), 1, CapnpType::Void));
+        union.add_variant(UnionVariant::new_group(
+            "circle".to_string(),
+            vec![Field::new("radius".to_string(), 2, CapnpType::Float32)],
+        ));
+        union.add_variant(UnionVariant::new_group(
+            "square".to_string(),
+            vec![Field::new("side".to_string(), 3, CapnpType::Float32)],
+        ));
+        let mut s = Struct::new("Shape".to_string());
+        s.set_union(union);
+
+        let options = RenderOptions {
+            blank_lines_between_groups: true,
+            ..RenderOptions::default()
+        };
+        let output = Schema::with_struct(s).render_with(&options).unwrap();
+
+        assert_eq!(
+            output,
+            "struct Shape {\n  union {\n    empty @0 :Void;\n    unknown @1 :Void;\n    \
+             circle :group {\n      radius @2 :Float32;\n    }\n\n    \
+             square :group {\n      side @3 :Float32;\n    }\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_field_order_by_id_is_default() {
+        let mut scrambled = Struct::new("Point".to_string());
+        scrambled.add_field(Field::new("z".to_string(), 2, CapnpType::Float32));
+        scrambled.add_field(Field::new("x".to_string(), 0, CapnpType::Float32));
+        scrambled.add_field(Field::new("y".to_string(), 1, CapnpType::Float32));
+
+        let mut ordered = Struct::new("Point".to_string());
+        ordered.add_field(Field::new("x".to_string(), 0, CapnpType::Float32));
+        ordered.add_field(Field::new("y".to_string(), 1, CapnpType::Float32));
+        ordered.add_field(Field::new("z".to_string(), 2, CapnpType::Float32));
+
+        let scrambled_doc = Schema::with_struct(scrambled.clone());
+        let ordered_doc = Schema::with_struct(ordered);
+        assert_eq!(scrambled_doc.field_order, FieldOrder::ById);
+        assert_eq!(
+            scrambled_doc.render().unwrap(),
+            ordered_doc.render().unwrap()
+        );
+        assert_eq!(
+            scrambled_doc.render().unwrap(),
+            "struct Point {\n  x @0 :Float32;\n  y @1 :Float32;\n  z @2 :Float32;\n}\n"
+        );
+        assert_eq!(scrambled.render().unwrap(), ordered_doc.render().unwrap());
+    }
+
+    #[test]
+    fn test_field_order_source_order() {
+        let mut s = Struct::new("Point".to_string());
+        s.add_field(Field::new("y".to_string(), 1, CapnpType::Float32));
+        s.add_field(Field::new("x".to_string(), 0, CapnpType::Float32));
+
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new_group(
+            "group".to_string(),
+            vec![
+                Field::new("b".to_string(), 3, CapnpType::Bool),
+                Field::new("a".to_string(), 2, CapnpType::Bool),
+            ],
+        ));
+        s.set_union(union);
+
+        let mut doc = Schema::with_struct(s);
+        doc.set_field_order(FieldOrder::SourceOrder);
+        let output = doc.render().unwrap();
+
+        assert!(output.contains("  y @1 :Float32;\n  x @0 :Float32;\n"));
+        assert!(output.contains("      b @3 :Bool;\n      a @2 :Bool;\n"));
+
+        doc.set_field_order(FieldOrder::ById);
+        let output = doc.render().unwrap();
+
+        assert!(output.contains("  x @0 :Float32;\n  y @1 :Float32;\n"));
+        assert!(output.contains("      a @2 :Bool;\n      b @3 :Bool;\n"));
+    }
+
+    #[test]
+    fn test_render_canonical_ignores_insertion_order() {
+        let mut a = Struct::new("A".to_string());
+        a.add_field(Field::new("y".to_string(), 1, CapnpType::Float32));
+        a.add_field(Field::new("x".to_string(), 0, CapnpType::Float32));
+        a.add_group(Group::new("zeta".to_string(), vec![]));
+        a.add_group(Group::new("alpha".to_string(), vec![]));
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new("later".to_string(), 3, CapnpType::Void));
+        union.add_variant(UnionVariant::new("earlier".to_string(), 2, CapnpType::Void));
+        a.set_union(union);
+
+        let mut scrambled = Schema::new();
+        scrambled.add_item(SchemaItem::Struct(Struct::new("B".to_string())));
+        scrambled.add_item(SchemaItem::Struct(a.clone()));
+        scrambled.set_field_order(FieldOrder::SourceOrder);
+
+        let mut ordered = Schema::new();
+        let mut a_ordered = Struct::new("A".to_string());
+        a_ordered.add_field(Field::new("x".to_string(), 0, CapnpType::Float32));
+        a_ordered.add_field(Field::new("y".to_string(), 1, CapnpType::Float32));
+        a_ordered.add_group(Group::new("alpha".to_string(), vec![]));
+        a_ordered.add_group(Group::new("zeta".to_string(), vec![]));
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new("earlier".to_string(), 2, CapnpType::Void));
+        union.add_variant(UnionVariant::new("later".to_string(), 3, CapnpType::Void));
+        a_ordered.set_union(union);
+        ordered.add_item(SchemaItem::Struct(a_ordered));
+        ordered.add_item(SchemaItem::Struct(Struct::new("B".to_string())));
+
+        assert_eq!(
+            scrambled.render_canonical().unwrap(),
+            ordered.render_canonical().unwrap()
+        );
+        assert_eq!(
+            ordered.render_canonical().unwrap(),
+            ordered.render().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_forbid_empty_structs() {
+        let mut doc = Schema::new();
+        doc.add_item(SchemaItem::Struct(Struct::new("Forgotten".to_string())));
+        let options = RenderOptions {
+            forbid_empty_structs: true,
+            ..RenderOptions::default()
+        };
+
+        assert_eq!(doc.render().unwrap(), "struct Forgotten {\n}\n");
+        assert_eq!(
+            doc.render_with(&options),
+            Err(ValidationError::EmptyStruct {
+                name: "Forgotten".to_string()
+            })
+        );
+
+        let mut marker = Struct::new("Marker".to_string());
+        marker.set_allow_empty(true);
+        let doc = Schema::with_struct(marker);
+        assert_eq!(doc.render_with(&options).unwrap(), "struct Marker {\n}\n");
+    }
+
+    // Struct tests
+    #[test]
+    fn test_empty_struct() {
+        let s = Struct::new("Empty".to_string());
+        let output = s.render().unwrap();
+
+        assert_eq!(output, "struct Empty {\n}\n");
+    }
+
+    #[test]
+    fn test_struct_new() {
+        let s = Struct::new("TestStruct".to_string());
+
+        assert_eq!(s.name, "TestStruct");
+        assert_eq!(s.fields.len(), 0);
+        assert!(s.union().is_none());
+    }
+
+    #[test]
+    fn test_generic_struct_rendering() {
+        let mut s = Struct::new("Pair".to_string());
+        s.add_generic_param("A".to_string());
+        s.add_generic_param("B".to_string());
+        s.add_field(Field::new(
+            "first".to_string(),
+            0,
+            CapnpType::GenericParam("A".to_string()),
+        ));
+        s.add_field(Field::new(
+            "second".to_string(),
+            1,
+            CapnpType::GenericParam("B".to_string()),
+        ));
+
+        let output = s.render().unwrap();
+        assert_eq!(
+            output,
+            "struct Pair(A, B) {\n  first @0 :A;\n  second @1 :B;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_struct_type_ref() {
+        let company = Struct::new("Company".to_string());
+        assert_eq!(
+            company.type_ref(),
+            CapnpType::UserDefined("Company".to_string())
+        );
+
+        let mut person = Struct::new("Person".to_string());
+        person.add_field(Field::new("employer".to_string(), 0, company.type_ref()));
+        assert!(person.render().unwrap().contains("employer @0 :Company;"));
+    }
+
+    #[test]
+    fn test_struct_add_field() {
+        let mut s = Struct::new("Test".to_string());
+        let field = Field::new("test".to_string(), 0, CapnpType::Bool);
+
+        s.add_field(field);
+        assert_eq!(s.fields.len(), 1);
+        assert_eq!(s.fields[0].name, "test");
+    }
+
+    #[test]
+    fn test_struct_set_union() {
+        let mut s = Struct::new("Test".to_string());
+        let union = Union::new();
+
+        s.set_union(union);
+        assert!(s.union().is_some());
+    }
+
+    #[test]
+    fn test_struct_with_group() {
+        let mut s = Struct::new("Shape".to_string());
+        s.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        s.add_group(Group::new(
+            "position".to_string(),
+            vec![
+                Field::new("x".to_string(), 1, CapnpType::Float32),
+                Field::new("y".to_string(), 2, CapnpType::Float32),
+            ],
+        ));
+
+        let output = s.render().unwrap();
+        assert_eq!(
+            output,
+            "struct Shape {\n  name @0 :Text;\n  position :group {\n    x @1 :Float32;\n    y @2 :Float32;\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_field_by_id() {
+        let mut s = Struct::new("Person".to_string());
+        s.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        s.add_group(Group::new(
+            "address".to_string(),
+            vec![Field::new("city".to_string(), 1, CapnpType::Text)],
+        ));
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new("unknown".to_string(), 2, CapnpType::Void));
+        union.add_variant(UnionVariant::new_group(
+            "known".to_string(),
+            vec![Field::new("year".to_string(), 3, CapnpType::UInt16)],
+        ));
+        s.set_union(union);
+
+        assert!(matches!(s.field_by_id(0), Some(IdLocation::Field(f)) if f.name == "name"));
+        assert!(matches!(
+            s.field_by_id(1),
+            Some(IdLocation::GroupMember { group, field })
+                if group.name == "address" && field.name == "city"
+        ));
+        assert!(
+            matches!(s.field_by_id(2), Some(IdLocation::UnionVariant(v)) if v.name == "unknown")
+        );
+        assert!(matches!(
+            s.field_by_id(3),
+            Some(IdLocation::UnionGroupMember { variant, field })
+                if variant.name == "known" && field.name == "year"
+        ));
+        assert_eq!(s.field_by_id(4), None);
+
+        let union = s.union().unwrap();
+        assert_eq!(union.variant_by_ordinal(2).unwrap().name, "unknown");
+        assert_eq!(union.variant_by_ordinal(3).unwrap().name, "known");
+        assert_eq!(union.variant_by_ordinal(0), None);
+    }
+
+    #[test]
+    fn test_nested_struct() {
+        let mut inner = Struct::new("Inner".to_string());
+        inner.add_field(Field::new("value".to_string(), 0, CapnpType::UInt32));
+
+        let mut outer = Struct::new("Outer".to_string());
+        outer.add_field(Field::new(
+            "inner".to_string(),
+            0,
+            CapnpType::UserDefined("Inner".to_string()),
+        ));
+
+        let mut doc = Schema::new();
+        doc.add_item(SchemaItem::Struct(outer));
+        doc.add_item(SchemaItem::Struct(inner));
+        assert!(doc.nest("Inner", "Outer"));
+        assert!(!doc.nest("Missing", "Outer"));
+
+        assert_eq!(doc.len(), 1);
+        assert!(doc.validate().is_ok());
+        assert_eq!(
+            doc.render().unwrap(),
+            "struct Outer {\n  struct Inner {\n    value @0 :UInt32;\n  }\n  inner @0 :Inner;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_helpers_qualify_nested_types() {
+        let mut doc = Schema::new();
+        for parent in ["Person", "Company"] {
+            let mut address = Struct::new("Address".to_string());
+            address.add_field(Field::new("city".to_string(), 0, CapnpType::Text));
+            let mut s = Struct::new(parent.to_string());
+            s.add_nested(SchemaItem::Struct(address));
+            s.add_field(Field::new(
+                "home".to_string(),
+                0,
+                CapnpType::Option(Box::new(CapnpType::UserDefined("Address".to_string()))),
+            ));
+            doc.add_item(SchemaItem::Struct(s));
+        }
+
+        assert_eq!(doc.validate(), Ok(()));
+        assert!(doc.validate_capnp_compatible().is_empty());
+        let output = doc.render().unwrap();
+        assert!(output.contains("  home @0 :OptionPersonAddress;\n"));
+        assert!(output.contains("  home @0 :OptionCompanyAddress;\n"));
+        assert!(output.contains(
+            "struct OptionPersonAddress {\n  union {\n    none @0 :Void;\n    some @1 :Person.Address;\n  }\n}\n"
+        ));
+        assert!(output.contains("    some @1 :Company.Address;\n"));
+    }
 
-    // Document tests
     #[test]
-    fn test_empty_document() {
-        let doc = Schema::new();
-        assert_eq!(doc.items.len(), 0);
-        assert_eq!(doc.render().unwrap(), "");
+    fn test_nest_qualifies_references_from_other_items() {
+        let address = CapnpType::UserDefined("Address".to_string());
+        let mut person = Struct::new("Person".to_string());
+        person.add_field(Field::new("home".to_string(), 0, address.clone()));
+        let mut company = Struct::new("Company".to_string());
+        company.add_field(Field::new("hq".to_string(), 0, address));
+        let mut doc = Schema::new();
+        doc.add_item(SchemaItem::Struct(Struct::new("Address".to_string())));
+        doc.add_item(SchemaItem::Struct(person));
+        doc.add_item(SchemaItem::Struct(company.clone()));
+
+        assert!(doc.nest("Address", "Person"));
+        assert_eq!(doc.validate(), Ok(()));
+        assert_eq!(doc.validate_capnp_compatible(), vec![]);
+        let output = doc.render().unwrap();
+        assert!(output.contains("  home @0 :Address;\n"));
+        assert!(output.contains("struct Company {\n  hq @0 :Person.Address;\n}\n"));
+
+        // Without the rewrite, the bare name doesn't resolve outside the parent
+        doc.items[1] = SchemaItem::Struct(company);
+        let undefined = ValidationError::UndefinedType {
+            name: "Address".to_string(),
+            referenced_by: "Company".to_string(),
+        };
+        assert_eq!(doc.validate(), Err(undefined.clone()));
+        assert_eq!(doc.validate_capnp_compatible(), vec![undefined]);
     }
 
     #[test]
-    fn test_document_default() {
-        let doc = Schema::default();
-        assert_eq!(doc.items.len(), 0);
+    fn test_language_annotations() {
+        let mut doc = Schema::with_struct(Struct::new("Empty".to_string()));
+        doc.add_language_annotation(LanguageAnnotation::CxxNamespace, "foo::bar".to_string());
+        doc.add_language_annotation(LanguageAnnotation::GoPackage, "foo".to_string());
+
+        assert_eq!(
+            doc.render().unwrap(),
+            "using Cxx = import \"/capnp/c++.capnp\";\nusing Go = import \"/go.capnp\";\n\
+             $Cxx.namespace(\"foo::bar\");\n$Go.package(\"foo\");\n\nstruct Empty {\n}\n"
+        );
     }
 
     #[test]
-    fn test_document_with_struct() {
-        let s = Struct::new("Test".to_string());
-        let doc = Schema::with_struct(s);
+    fn test_version_comment() {
+        let mut doc = Schema::with_struct(Struct::new("Empty".to_string()));
+        doc.add_language_annotation(LanguageAnnotation::GoPackage, "foo".to_string());
+        doc.set_version(7);
 
-        assert_eq!(doc.items.len(), 1);
-        assert!(matches!(doc.items[0], SchemaItem::Struct(_)));
+        let output = doc.render().unwrap();
+        assert!(output.starts_with("# schema version: 7\nusing Go = import \"/go.capnp\";\n"));
+        assert_eq!(doc.render_canonical().unwrap(), output);
+
+        let mut bare = Schema::with_struct(Struct::new("Empty".to_string()));
+        bare.set_version(7);
+        assert_eq!(
+            bare.render().unwrap(),
+            "# schema version: 7\n\nstruct Empty {\n}\n"
+        );
     }
 
     #[test]
-    fn test_document_add_item() {
+    fn test_rename_type() {
+        let person = CapnpType::UserDefined("Person".to_string());
+        let mut company = Struct::new("Company".to_string());
+        company.add_field(Field::new(
+            "employees".to_string(),
+            0,
+            CapnpType::List(Box::new(person.clone())),
+        ));
+        company.add_field(Field::new(
+            "founder".to_string(),
+            1,
+            CapnpType::Option(Box::new(person.clone())),
+        ));
+        let mut ceo = Union::new();
+        ceo.set_name("ceo".to_string());
+        ceo.add_variant(UnionVariant::new("vacant".to_string(), 2, CapnpType::Void));
+        ceo.add_variant(UnionVariant::new("person".to_string(), 3, person));
+        company.add_union(ceo);
+
         let mut doc = Schema::new();
-        let s = Struct::new("Test".to_string());
+        doc.add_item(SchemaItem::Struct(company));
+        doc.add_item(SchemaItem::Struct(Struct::new("Person".to_string())));
+
+        assert_eq!(
+            doc.rename_type("Person", "Company"),
+            Err(ValidationError::DuplicateTypeName {
+                name: "Company".to_string()
+            })
+        );
+        assert_eq!(doc.rename_type("Person", "Employee"), Ok(3));
+        assert_eq!(doc.kind_of("Person"), None);
+        assert_eq!(doc.kind_of("Employee"), Some(TypeKind::Struct));
 
-        doc.add_item(SchemaItem::Struct(s));
-        assert_eq!(doc.items.len(), 1);
+        let output = doc.render().unwrap();
+        assert!(output.contains("employees @0 :List(Employee);"));
+        assert!(output.contains("founder @1 :OptionEmployee;"));
+        assert!(output.contains("person @3 :Employee;"));
+        assert!(!output.contains("Person"));
     }
 
     #[test]
-    fn test_multiple_structs_with_spacing() {
-        let mut doc = Schema::new();
-
-        let s1 = Struct::new("Person".to_string());
-        let s2 = Struct::new("Company".to_string());
-
-        doc.add_item(SchemaItem::Struct(s1));
-        doc.add_item(SchemaItem::Struct(s2));
-
-        let output = doc.render().unwrap();
+    fn test_errors_name_the_owning_type() {
+        let mut s = Struct::new("UserProfile".to_string());
+        s.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        s.add_field(Field::new("email".to_string(), 0, CapnpType::Text));
+        assert_eq!(
+            s.validate().unwrap_err().to_string(),
+            "In 'UserProfile': Duplicate ID 0 found in: struct field 'name', struct field 'email'"
+        );
+
+        let mut s = Struct::new("Settings".to_string());
+        let mut retries = Field::new("retries".to_string(), 0, CapnpType::UInt8);
+        retries.set_default(DefaultValue::Int(300));
+        assert!(
+            retries
+                .validate()
+                .unwrap_err()
+                .to_string()
+                .starts_with("Invalid default")
+        );
+        s.add_field(retries);
+        assert_eq!(
+            s.validate().unwrap_err().to_string(),
+            "In 'Settings': Invalid default value for field 'retries': 300 is out of range for UInt8"
+        );
+
+        let mut interface = Interface::new("Calculator".to_string());
+        interface.add_method(Method::new("add".to_string(), 0));
+        interface.add_method(Method::new("sub".to_string(), 0));
+        assert!(
+            interface
+                .validate()
+                .unwrap_err()
+                .to_string()
+                .starts_with("In 'Calculator': ")
+        );
+    }
 
-        assert!(output.contains("struct Person {"));
-        assert!(output.contains("struct Company {"));
-        // Test that there's spacing between structs
-        assert!(output.contains("}\n\nstruct Company"));
+    #[test]
+    fn test_extra_field_reusing_live_id() {
+        let mut s = Struct::new("UserProfile".to_string());
+        s.add_field(Field::new("username".to_string(), 0, CapnpType::Text));
+        s.add_field(Field::new("email".to_string(), 1, CapnpType::Text));
+        s.add_extra_field("oldUserId @1 :UInt64".to_string());
+        s.add_extra_field("deprecatedFlag @3 :Bool".to_string());
+
+        let Err(ValidationError::DuplicateId { id, locations, .. }) = s.validate() else {
+            panic!("Expected DuplicateId error");
+        };
+        assert_eq!(id, 1);
+        assert_eq!(
+            locations,
+            vec![
+                "struct field 'email'".to_string(),
+                "extra field 'oldUserId'".to_string()
+            ]
+        );
+
+        assert_eq!(
+            parse_extra_field("deprecatedFlag @3 :Bool"),
+            Some(("deprecatedFlag", 3))
+        );
+        assert_eq!(parse_extra_field("struct Old {}"), None);
     }
 
-    // Struct tests
     #[test]
-    fn test_empty_struct() {
-        let s = Struct::new("Empty".to_string());
-        let output = s.render().unwrap();
+    fn test_named_union() {
+        let mut union = Union::new();
+        union.set_name("kind".to_string());
+        union.add_variant(UnionVariant::new(
+            "circle".to_string(),
+            1,
+            CapnpType::Float64,
+        ));
+        union.add_variant(UnionVariant::new(
+            "square".to_string(),
+            2,
+            CapnpType::Float64,
+        ));
 
-        assert_eq!(output, "struct Empty {\n}\n");
+        let mut s = Struct::new("Shape".to_string());
+        s.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        s.set_union(union);
+
+        assert_eq!(
+            s.render().unwrap(),
+            "struct Shape {\n  name @0 :Text;\n  kind :union {\n    circle @1 :Float64;\n    square @2 :Float64;\n  }\n}\n"
+        );
     }
 
     #[test]
-    fn test_struct_new() {
-        let s = Struct::new("TestStruct".to_string());
+    fn test_multiple_unions() {
+        let mut shape = Union::new();
+        shape.set_name("shape".to_string());
+        shape.add_variant(UnionVariant::new(
+            "circle".to_string(),
+            1,
+            CapnpType::Float64,
+        ));
+        shape.add_variant(UnionVariant::new(
+            "square".to_string(),
+            2,
+            CapnpType::Float64,
+        ));
 
-        assert_eq!(s.name, "TestStruct");
-        assert_eq!(s.fields.len(), 0);
-        assert!(s.union.is_none());
+        let mut fill = Union::new();
+        fill.set_name("fill".to_string());
+        fill.add_variant(UnionVariant::new("none".to_string(), 3, CapnpType::Void));
+        fill.add_variant(UnionVariant::new("color".to_string(), 4, CapnpType::UInt32));
+
+        let mut s = Struct::new("Drawing".to_string());
+        s.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        s.add_union(shape);
+        s.add_union(fill);
+
+        assert_eq!(s.union().unwrap().name.as_deref(), Some("shape"));
+        assert_eq!(
+            s.render().unwrap(),
+            "struct Drawing {\n  name @0 :Text;\n  shape :union {\n    circle @1 :Float64;\n    square @2 :Float64;\n  }\n  \
+             fill :union {\n    none @3 :Void;\n    color @4 :UInt32;\n  }\n}\n"
+        );
+
+        // The unions share the struct's ID space
+        let mut clashing = Union::new();
+        clashing.set_name("other".to_string());
+        clashing.add_variant(UnionVariant::new("dup".to_string(), 4, CapnpType::Void));
+        clashing.add_variant(UnionVariant::new("fine".to_string(), 5, CapnpType::Void));
+        s.add_union(clashing);
+        assert!(matches!(
+            s.validate(),
+            Err(ValidationError::DuplicateId { id: 4, .. })
+        ));
+
+        let mut anonymous = Struct::new("Anonymous".to_string());
+        anonymous.add_union(Union::new());
+        anonymous.add_union(Union::new());
+        assert_eq!(
+            anonymous.validate(),
+            Err(ValidationError::MultipleAnonymousUnions {
+                type_name: "Anonymous".to_string()
+            })
+        );
     }
 
     #[test]
-    fn test_struct_add_field() {
-        let mut s = Struct::new("Test".to_string());
-        let field = Field::new("test".to_string(), 0, CapnpType::Bool);
-
-        s.add_field(field);
-        assert_eq!(s.fields.len(), 1);
-        assert_eq!(s.fields[0].name, "test");
+    fn test_union_ordinal_gap() {
+        let mut status = Union::new();
+        status.add_variant(UnionVariant::new("idle".to_string(), 0, CapnpType::Void));
+        status.add_variant(UnionVariant::new("busy".to_string(), 2, CapnpType::Void));
+        let mut s = Struct::new("Status".to_string());
+        s.set_union(status.clone());
+        assert_eq!(
+            s.validate(),
+            Err(ValidationError::UnionOrdinalGap {
+                type_name: "Status".to_string(),
+                union_name: None,
+                missing: 1,
+            })
+        );
+        assert_eq!(
+            s.validate().unwrap_err().to_string(),
+            "In 'Status': Union skips ordinal 1; union ordinals can't have gaps"
+        );
+
+        // A field can fill the gap, and a group variant's members count as ordinals
+        let mut filled = s.clone();
+        filled.add_field(Field::new("since".to_string(), 1, CapnpType::UInt64));
+        assert_eq!(filled.validate(), Ok(()));
+
+        let mut grouped = Struct::new("Status".to_string());
+        status.add_variant(UnionVariant::new_group(
+            "failed".to_string(),
+            vec![Field::new("reason".to_string(), 1, CapnpType::Text)],
+        ));
+        grouped.set_union(status);
+        assert_eq!(grouped.validate(), Ok(()));
+
+        // Ordinals below a union that doesn't start the struct count as well
+        let mut late = Union::new();
+        late.set_name("late".to_string());
+        late.add_variant(UnionVariant::new("a".to_string(), 3, CapnpType::Void));
+        late.add_variant(UnionVariant::new("b".to_string(), 4, CapnpType::Void));
+        let mut s = Struct::new("Late".to_string());
+        s.add_field(Field::new("first".to_string(), 0, CapnpType::Text));
+        s.add_union(late);
+        assert_eq!(
+            s.validate(),
+            Err(ValidationError::UnionOrdinalGap {
+                type_name: "Late".to_string(),
+                union_name: Some("late".to_string()),
+                missing: 1,
+            })
+        );
     }
 
     #[test]
-    fn test_struct_set_union() {
-        let mut s = Struct::new("Test".to_string());
-        let union = Union::new();
+    fn test_duplicate_id_between_field_and_group_member() {
+        let mut s = Struct::new("Shape".to_string());
+        s.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        s.add_group(Group::new(
+            "position".to_string(),
+            vec![Field::new("x".to_string(), 0, CapnpType::Float32)],
+        ));
 
-        s.set_union(union);
-        assert!(s.union.is_some());
+        let Err(ValidationError::DuplicateId { id, locations, .. }) = s.validate() else {
+            panic!("Expected DuplicateId error");
+        };
+        assert_eq!(id, 0);
+        assert!(locations.contains(&"struct field 'name'".to_string()));
+        assert!(locations.contains(&"struct group 'position' field 'x'".to_string()));
     }
 
     #[test]
@@ -539,6 +4281,37 @@ mod tests {
         assert_eq!(output, "  union {\n  }\n");
     }
 
+    #[test]
+    fn test_union_render_at_depth() {
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new("none".to_string(), 0, CapnpType::Void));
+        union.add_variant(UnionVariant::new_group(
+            "point".to_string(),
+            vec![
+                Field::new("x".to_string(), 1, CapnpType::Int32),
+                Field::new("y".to_string(), 2, CapnpType::Int32),
+            ],
+        ));
+
+        assert_eq!(
+            union.render_at_depth(0),
+            "union {\n  none @0 :Void;\n  point :group {\n    x @1 :Int32;\n    y @2 :Int32;\n  }\n}\n"
+        );
+        assert_eq!(
+            union.render_at_depth(2),
+            "    union {\n      none @0 :Void;\n      point :group {\n        x @1 :Int32;\n        y @2 :Int32;\n      }\n    }\n"
+        );
+
+        let mut inner = Struct::new("Inner".to_string());
+        inner.set_union(union);
+        let mut outer = Struct::new("Outer".to_string());
+        outer.add_nested(SchemaItem::Struct(inner));
+        assert_eq!(
+            outer.render().unwrap(),
+            "struct Outer {\n  struct Inner {\n    union {\n      none @0 :Void;\n      point :group {\n        x @1 :Int32;\n        y @2 :Int32;\n      }\n    }\n  }\n}\n"
+        );
+    }
+
     // Union variant tests
     #[test]
     fn test_union_variant_render() {
@@ -584,9 +4357,88 @@ mod tests {
         assert_eq!(CapnpType::Float32.render(), "Float32");
         assert_eq!(CapnpType::Float64.render(), "Float64");
         assert_eq!(CapnpType::Text.render(), "Text");
+        assert_eq!(CapnpType::Data.render(), "Data");
         assert_eq!(CapnpType::Void.render(), "Void");
     }
 
+    #[test]
+    fn test_suggested_rust_type() {
+        assert_eq!(CapnpType::UInt32.suggested_rust_type(), "u32");
+        assert_eq!(CapnpType::Float64.suggested_rust_type(), "f64");
+        assert_eq!(CapnpType::Text.suggested_rust_type(), "String");
+        assert_eq!(CapnpType::Data.suggested_rust_type(), "Vec<u8>");
+        assert_eq!(CapnpType::Void.suggested_rust_type(), "()");
+        assert_eq!(
+            CapnpType::List(Box::new(CapnpType::Text)).suggested_rust_type(),
+            "Vec<String>"
+        );
+        assert_eq!(
+            CapnpType::List(Box::new(CapnpType::List(Box::new(CapnpType::Data))))
+                .suggested_rust_type(),
+            "Vec<Vec<Vec<u8>>>"
+        );
+        assert_eq!(
+            CapnpType::Result(
+                Box::new(CapnpType::Option(Box::new(CapnpType::Int8))),
+                Box::new(CapnpType::UserDefined("Error".to_string())),
+            )
+            .suggested_rust_type(),
+            "Result<Option<i8>, Error>"
+        );
+    }
+
+    #[test]
+    fn test_parse_type() {
+        assert_eq!(CapnpType::parse("UInt16"), Some(CapnpType::UInt16));
+        assert_eq!(CapnpType::parse("Void"), Some(CapnpType::Void));
+        assert_eq!(
+            CapnpType::parse("List(List(Data))"),
+            Some(CapnpType::List(Box::new(CapnpType::List(Box::new(
+                CapnpType::Data
+            )))))
+        );
+        assert_eq!(
+            CapnpType::parse("Outer.Inner"),
+            Some(CapnpType::UserDefined("Outer.Inner".to_string()))
+        );
+        assert_eq!(CapnpType::parse("uint16"), None);
+        assert_eq!(CapnpType::parse("List(UInt16"), None);
+        assert_eq!(CapnpType::parse("List()"), None);
+        assert_eq!(CapnpType::parse("Map<Text>"), None);
+    }
+
+    #[test]
+    fn test_sort_types() {
+        let list = |inner| CapnpType::List(Box::new(inner));
+        let user = |name: &str| CapnpType::UserDefined(name.to_string());
+        let mut types = vec![
+            user("Person"),
+            list(user("Address")),
+            CapnpType::Text,
+            list(CapnpType::Bool),
+            user("Address"),
+            CapnpType::Bool,
+            list(list(CapnpType::Bool)),
+        ];
+        types.sort();
+
+        assert_eq!(
+            types,
+            vec![
+                CapnpType::Bool,
+                CapnpType::Text,
+                list(CapnpType::Bool),
+                list(list(CapnpType::Bool)),
+                list(user("Address")),
+                user("Address"),
+                user("Person"),
+            ]
+        );
+
+        let unique: BTreeSet<CapnpType> = types.into_iter().chain([CapnpType::Bool]).collect();
+        assert_eq!(unique.len(), 7);
+    }
+
     #[test]
     fn test_user_defined_type() {
         let user_type = CapnpType::UserDefined("MyCustomType".to_string());
@@ -616,6 +4468,125 @@ mod tests {
         assert_eq!(deeply_nested.render(), "List(List(List(Bool)))");
     }
 
+    #[test]
+    fn test_with_dependencies_handles_mutual_recursion() {
+        let mut a = Struct::new("A".to_string());
+        a.add_field(Field::new(
+            "b".to_string(),
+            0,
+            CapnpType::UserDefined("B".to_string()),
+        ));
+        let mut b = Struct::new("B".to_string());
+        b.add_field(Field::new(
+            "a".to_string(),
+            0,
+            CapnpType::UserDefined("A".to_string()),
+        ));
+        b.add_field(Field::new(
+            "children".to_string(),
+            1,
+            CapnpType::List(Box::new(CapnpType::UserDefined("B".to_string()))),
+        ));
+        let unrelated = Struct::new("Unrelated".to_string());
+
+        let pool = vec![
+            SchemaItem::Struct(unrelated),
+            SchemaItem::Struct(b),
+            SchemaItem::Struct(a),
+        ];
+        let doc = Schema::with_dependencies(&["A"], &pool);
+
+        let names: Vec<&str> = doc.items.iter().map(SchemaItem::name).collect();
+        assert_eq!(names, vec!["A", "B"]);
+
+        let output = doc.render().unwrap();
+        assert_eq!(output.matches("struct A {").count(), 1);
+        assert_eq!(output.matches("struct B {").count(), 1);
+    }
+
+    #[test]
+    fn test_transparent_items_are_inlined() {
+        let mut account = Struct::new("Account".to_string());
+        account.add_field(Field::new(
+            "owner".to_string(),
+            0,
+            CapnpType::UserDefined("UserId".to_string()),
+        ));
+        account.add_field(Field::new(
+            "friends".to_string(),
+            1,
+            CapnpType::List(Box::new(CapnpType::UserDefined("UserId".to_string()))),
+        ));
+
+        let user_id = SchemaItem::Transparent(Transparent {
+            name: "UserId".to_string(),
+            target: CapnpType::UInt64,
+        });
+        assert_eq!(user_id.render().unwrap(), "using UserId = UInt64;\n");
+
+        let mut doc = Schema::new();
+        doc.add_item(user_id);
+        doc.add_item(SchemaItem::Struct(account));
+
+        assert_eq!(
+            doc.render().unwrap(),
+            "struct Account {\n  owner @0 :UInt64;\n  friends @1 :List(UInt64);\n}\n"
+        );
+    }
+
+    // File ID tests
+    #[test]
+    fn test_file_id_round_trip() {
+        let id = 0xfbb45a811fbe71f5;
+        assert_eq!(format_file_id(id), "@0xfbb45a811fbe71f5;");
+        assert_eq!(parse_file_id(&format_file_id(id)), Some(id));
+        assert_eq!(parse_file_id("  0xfbb45a811fbe71f5\n"), Some(id));
+
+        assert_eq!(parse_file_id("@fbb45a811fbe71f5;"), None);
+        assert_eq!(parse_file_id("@0x;"), None);
+        assert_eq!(parse_file_id("@0x+1;"), None);
+        assert_eq!(parse_file_id("@0x1fbb45a811fbe71f5;"), None);
+    }
+
+    #[test]
+    fn test_file_id_high_bit() {
+        assert_eq!(check_file_id(0xfbb45a811fbe71f5), Ok(()));
+        assert_eq!(
+            check_file_id(0x42),
+            Err(ValidationError::InvalidFileId { id: 0x42 })
+        );
+        assert_eq!(
+            check_file_id(0x42).unwrap_err().to_string(),
+            "File ID 0x42 must have its high bit set (e.g. 0x8000000000000042)"
+        );
+        assert_eq!(check_file_id(with_high_bit(0x42)), Ok(()));
+        assert_eq!(with_high_bit(0xfbb45a811fbe71f5), 0xfbb45a811fbe71f5);
+    }
+
+    #[test]
+    fn test_stable_file_id_is_deterministic() {
+        let mut doc = Schema::new();
+        doc.add_item(SchemaItem::Struct(Struct::new("Person".to_string())));
+        doc.add_item(SchemaItem::Struct(Struct::new("Company".to_string())));
+
+        let mut reordered = Schema::new();
+        reordered.add_item(SchemaItem::Struct(Struct::new("Company".to_string())));
+        reordered.add_item(SchemaItem::Struct(Struct::new("Person".to_string())));
+
+        let id = stable_file_id(&doc);
+        assert_eq!(id, stable_file_id(&doc));
+        assert_eq!(id, stable_file_id(&reordered));
+        assert_ne!(id & (1 << 63), 0);
+    }
+
+    #[test]
+    fn test_stable_file_id_differs_between_schemas() {
+        let doc1 = Schema::with_struct(Struct::new("Person".to_string()));
+        let doc2 = Schema::with_struct(Struct::new("Company".to_string()));
+
+        assert_ne!(stable_file_id(&doc1), stable_file_id(&doc2));
+    }
+
     // Integration tests
     #[test]
     fn test_simple_struct_rendering() {
@@ -657,6 +4628,45 @@ mod tests {
         assert!(output.contains("}"));
     }
 
+    #[test]
+    fn test_id_usages() {
+        let mut s = Struct::new("ComplexMessage".to_string());
+        s.add_field(Field::new("sent".to_string(), 0, CapnpType::UInt64));
+        s.add_group(Group::new(
+            "origin".to_string(),
+            vec![Field::new("host".to_string(), 1, CapnpType::Text)],
+        ));
+        s.add_extra_field("legacy @2 :Text".to_string());
+
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new("unit".to_string(), 3, CapnpType::Void));
+        union.add_variant(UnionVariant::new_group(
+            "tuple".to_string(),
+            vec![
+                Field::new("field0".to_string(), 4, CapnpType::UInt32),
+                Field::new("field1".to_string(), 5, CapnpType::Text),
+            ],
+        ));
+        s.set_union(union);
+
+        let usages = s.id_usages();
+        let usages: Vec<(u32, &str)> = usages
+            .iter()
+            .map(|(id, location)| (*id, location.as_str()))
+            .collect();
+        assert_eq!(
+            usages,
+            vec![
+                (0, "struct field 'sent'"),
+                (1, "struct group 'origin' field 'host'"),
+                (2, "extra field 'legacy'"),
+                (3, "union variant 'unit'"),
+                (4, "union group 'tuple' field 'field0'"),
+                (5, "union group 'tuple' field 'field1'"),
+            ]
+        );
+    }
+
     #[test]
     fn test_union_struct_with_groups_rendering() {
         let mut s = Struct::new("ComplexMessage".to_string());
@@ -671,8 +4681,8 @@ mod tests {
         union.add_variant(UnionVariant::new_group("tuple".to_string(), tuple_fields));
 
         let struct_fields = vec![
-            Field::new("id".to_string(), 4, CapnpType::UInt64),
-            Field::new("name".to_string(), 5, CapnpType::Text),
+            Field::new("id".to_string(), 3, CapnpType::UInt64),
+            Field::new("name".to_string(), 4, CapnpType::Text),
         ];
         union.add_variant(UnionVariant::new_group("struct".to_string(), struct_fields));
 
@@ -688,8 +4698,8 @@ mod tests {
         assert!(output.contains("field0 @1 :UInt32;"));
         assert!(output.contains("field1 @2 :Text;"));
         assert!(output.contains("struct :group {"));
-        assert!(output.contains("id @4 :UInt64;"));
-        assert!(output.contains("name @5 :Text;"));
+        assert!(output.contains("id @3 :UInt64;"));
+        assert!(output.contains("name @4 :Text;"));
     }
 
     #[test]
@@ -774,7 +4784,7 @@ mod tests {
         let result = s.validate();
         assert!(result.is_err());
 
-        if let Err(ValidationError::DuplicateId { id, locations }) = result {
+        if let Err(ValidationError::DuplicateId { id, locations, .. }) = result {
             assert_eq!(id, 0);
             assert_eq!(locations.len(), 2);
             assert!(locations.contains(&"struct field 'field1'".to_string()));
@@ -800,7 +4810,7 @@ mod tests {
         let result = s.validate();
         assert!(result.is_err());
 
-        if let Err(ValidationError::DuplicateId { id, locations }) = result {
+        if let Err(ValidationError::DuplicateId { id, locations, .. }) = result {
             assert_eq!(id, 0);
             assert_eq!(locations.len(), 2);
             assert!(locations.contains(&"struct field 'field1'".to_string()));
@@ -826,7 +4836,7 @@ mod tests {
         let result = s.validate();
         assert!(result.is_err());
 
-        if let Err(ValidationError::DuplicateId { id, locations }) = result {
+        if let Err(ValidationError::DuplicateId { id, locations, .. }) = result {
             assert_eq!(id, 0);
             assert_eq!(locations.len(), 2);
             assert!(locations.contains(&"union group 'group1' field 'groupField1'".to_string()));
@@ -854,9 +4864,9 @@ mod tests {
         let result = s.validate();
         assert!(result.is_err());
 
-        // Should return error for the first duplicate found (order may vary due to HashMap)
-        if let Err(ValidationError::DuplicateId { id, locations }) = result {
-            assert!(id == 0 || id == 1);
+        // Should return error for the lowest duplicate ID
+        if let Err(ValidationError::DuplicateId { id, locations, .. }) = result {
+            assert_eq!(id, 0);
             assert_eq!(locations.len(), 2);
         } else {
             panic!("Expected DuplicateId error");
@@ -878,6 +4888,27 @@ mod tests {
         assert!(doc.validate().is_ok());
     }
 
+    #[test]
+    fn test_duplicate_type_names() {
+        let mut doc = Schema::new();
+
+        let mut s1 = Struct::new("Foo".to_string());
+        s1.add_field(Field::new("a".to_string(), 0, CapnpType::UInt32));
+        doc.add_item(SchemaItem::Struct(s1));
+
+        let mut s2 = Struct::new("Foo".to_string());
+        s2.add_field(Field::new("b".to_string(), 0, CapnpType::Text));
+        doc.add_item(SchemaItem::Struct(s2));
+
+        assert_eq!(
+            doc.validate(),
+            Err(ValidationError::DuplicateTypeName {
+                name: "Foo".to_string()
+            })
+        );
+        assert!(doc.render().is_err());
+    }
+
     #[test]
     fn test_duplicate_ids_between_different_union_groups() {
         let mut s = Struct::new("InvalidStruct".to_string());
@@ -910,7 +4941,7 @@ mod tests {
         let result = s.validate();
         assert!(result.is_err());
 
-        if let Err(ValidationError::DuplicateId { id, locations }) = result {
+        if let Err(ValidationError::DuplicateId { id, locations, .. }) = result {
             assert_eq!(id, 1);
             assert_eq!(locations.len(), 2);
             assert!(locations.contains(&"union group 'dimensions' field 'width'".to_string()));
@@ -935,13 +4966,203 @@ mod tests {
         s.set_union(union);
 
         let err = s.validate().unwrap_err();
-        let ValidationError::DuplicateId { id, locations } = err;
+        let ValidationError::DuplicateId { id, locations, .. } = err else {
+            panic!("Expected DuplicateId error");
+        };
         assert_eq!(id, 0);
         assert_eq!(locations.len(), 2);
         assert!(locations.contains(&"union variant 'groupA'".to_string()));
         assert!(locations.contains(&"union group 'groupB' field 'y'".to_string()));
     }
 
+    #[test]
+    fn test_group_member_id_collides_with_unit_variant() {
+        let mut s = Struct::new("Shape".to_string());
+        s.add_field(Field::new("name".to_string(), 0, CapnpType::Text));
+        s.add_group(Group::new(
+            "position".to_string(),
+            vec![
+                Field::new("x".to_string(), 1, CapnpType::Float32),
+                Field::new("y".to_string(), 2, CapnpType::Float32),
+            ],
+        ));
+
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new("hidden".to_string(), 2, CapnpType::Void));
+        union.add_variant(UnionVariant::new_group(
+            "tinted".to_string(),
+            vec![Field::new("color".to_string(), 3, CapnpType::UInt32)],
+        ));
+        s.set_union(union);
+
+        let Err(ValidationError::DuplicateId { id, locations, .. }) = s.validate() else {
+            panic!("Expected DuplicateId error");
+        };
+        assert_eq!(id, 2);
+        assert_eq!(
+            locations,
+            vec![
+                "struct group 'position' field 'y'".to_string(),
+                "union variant 'hidden'".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_option_helper_structs() {
+        let mut s = Struct::new("Profile".to_string());
+        s.add_field(Field::new(
+            "age".to_string(),
+            0,
+            CapnpType::Option(Box::new(CapnpType::UInt32)),
+        ));
+        s.add_field(Field::new(
+            "scores".to_string(),
+            1,
+            CapnpType::List(Box::new(CapnpType::Option(Box::new(CapnpType::UInt32)))),
+        ));
+        s.add_field(Field::new(
+            "tags".to_string(),
+            2,
+            CapnpType::Option(Box::new(CapnpType::List(Box::new(CapnpType::Text)))),
+        ));
+
+        let output = Schema::with_struct(s).render().unwrap();
+        assert_eq!(
+            output,
+            "struct Profile {\n  age @0 :OptionUInt32;\n  scores @1 :List(OptionUInt32);\n  tags @2 :OptionListText;\n}\n\n\
+             struct OptionListText {\n  union {\n    none @0 :Void;\n    some @1 :List(Text);\n  }\n}\n\n\
+             struct OptionUInt32 {\n  union {\n    none @0 :Void;\n    some @1 :UInt32;\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_helper_structs_are_sorted_by_name() {
+        let helper_types = [
+            CapnpType::Result(Box::new(CapnpType::Text), Box::new(CapnpType::Text)),
+            CapnpType::Option(Box::new(CapnpType::UInt64)),
+            CapnpType::Option(Box::new(CapnpType::Data)),
+        ];
+        let render_with_order = |order: &[usize]| {
+            let mut s = Struct::new("Lookup".to_string());
+            for (id, &index) in order.iter().enumerate() {
+                s.add_field(Field::new(
+                    format!("field{}", index),
+                    id as u32,
+                    helper_types[index].clone(),
+                ));
+            }
+            let output = Schema::with_struct(s).render().unwrap();
+            // Only the helpers, which follow the struct itself
+            output.split_once("}\n\n").unwrap().1.to_string()
+        };
+
+        let helpers = render_with_order(&[0, 1, 2]);
+        assert_eq!(helpers, render_with_order(&[2, 1, 0]));
+        assert_eq!(helpers, render_with_order(&[1, 0, 2]));
+
+        let names: Vec<&str> = helpers
+            .lines()
+            .filter_map(|line| line.strip_prefix("struct "))
+            .collect();
+        assert_eq!(
+            names,
+            vec!["OptionData {", "OptionUInt64 {", "ResultTextText {"]
+        );
+    }
+
+    #[test]
+    fn test_result_helper_struct() {
+        let mut s = Struct::new("Event".to_string());
+        s.add_field(Field::new(
+            "outcome".to_string(),
+            0,
+            CapnpType::Result(Box::new(CapnpType::UInt32), Box::new(CapnpType::Text)),
+        ));
+        s.add_field(Field::new(
+            "ack".to_string(),
+            1,
+            CapnpType::Result(
+                Box::new(CapnpType::Void),
+                Box::new(CapnpType::Option(Box::new(CapnpType::Text))),
+            ),
+        ));
+
+        let output = Schema::with_struct(s).render().unwrap();
+        assert_eq!(
+            output,
+            "struct Event {\n  outcome @0 :ResultUInt32Text;\n  ack @1 :ResultVoidOptionText;\n}\n\n\
+             struct OptionText {\n  union {\n    none @0 :Void;\n    some @1 :Text;\n  }\n}\n\n\
+             struct ResultUInt32Text {\n  union {\n    ok @0 :UInt32;\n    err @1 :Text;\n  }\n}\n\n\
+             struct ResultVoidOptionText {\n  union {\n    ok @0 :Void;\n    err @1 :OptionText;\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_option_helper_name_collision() {
+        let mut s = Struct::new("Profile".to_string());
+        s.add_field(Field::new(
+            "age".to_string(),
+            0,
+            CapnpType::Option(Box::new(CapnpType::UInt32)),
+        ));
+        let items = [
+            SchemaItem::Struct(s),
+            SchemaItem::Struct(Struct::new("OptionUInt32".to_string())),
+        ];
+
+        assert_eq!(
+            render_items(&items),
+            Err(ValidationError::DuplicateTypeName {
+                name: "OptionUInt32".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_field_integer_default() {
+        let mut field = Field::new("count".to_string(), 0, CapnpType::UInt32);
+        field.set_default(DefaultValue::Int(10));
+        assert_eq!(field.render(), "count @0 :UInt32 = 10;");
+
+        let mut s = Struct::new("Counter".to_string());
+        let mut out_of_range = Field::new("small".to_string(), 1, CapnpType::UInt8);
+        out_of_range.set_default(DefaultValue::Int(256));
+        s.add_field(field);
+        s.add_field(out_of_range);
+
+        let Err(ValidationError::InvalidDefault { field, .. }) = s.render() else {
+            panic!("Expected InvalidDefault error");
+        };
+        assert_eq!(field, "small");
+
+        let mut max = Field::new("max".to_string(), 0, CapnpType::UInt64);
+        max.set_default(DefaultValue::UInt(u64::MAX));
+        assert!(max.validate().is_ok());
+        assert_eq!(max.render(), "max @0 :UInt64 = 18446744073709551615;");
+
+        let mut signed = Field::new("signed".to_string(), 0, CapnpType::Int64);
+        signed.set_default(DefaultValue::UInt(u64::MAX));
+        let Err(ValidationError::InvalidDefault { reason, .. }) = signed.validate() else {
+            panic!("Expected InvalidDefault error");
+        };
+        assert_eq!(reason, "18446744073709551615 is out of range for Int64");
+    }
+
+    #[test]
+    fn test_field_text_default() {
+        let mut field = Field::new("greeting".to_string(), 0, CapnpType::Text);
+        field.set_default(DefaultValue::Text("say \"hi\"".to_string()));
+        assert_eq!(field.render(), r#"greeting @0 :Text = "say \"hi\"";"#);
+
+        let mut mismatched = Field::new("flag".to_string(), 1, CapnpType::Bool);
+        mismatched.set_default(DefaultValue::Text("yes".to_string()));
+        assert!(matches!(
+            mismatched.validate(),
+            Err(ValidationError::InvalidDefault { .. })
+        ));
+    }
+
     // Tests for automatic validation during rendering
     #[test]
     fn test_render_validation_failure_struct() {
@@ -952,7 +5173,7 @@ mod tests {
         let result = s.render();
         assert!(result.is_err());
 
-        if let Err(ValidationError::DuplicateId { id, locations }) = result {
+        if let Err(ValidationError::DuplicateId { id, locations, .. }) = result {
             assert_eq!(id, 0);
             assert_eq!(locations.len(), 2);
         } else {
@@ -982,4 +5203,130 @@ mod tests {
             panic!("Expected DuplicateId error during document render");
         }
     }
+
+    #[test]
+    fn test_try_into_native_enum_converts_void_union() {
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new("active".to_string(), 0, CapnpType::Void));
+        union.add_variant(UnionVariant::new(
+            "suspended".to_string(),
+            1,
+            CapnpType::Void,
+        ));
+        let mut status = Struct::new("Status".to_string());
+        status.set_union(union);
+
+        let item = SchemaItem::Struct(status).try_into_native_enum().unwrap();
+        assert_eq!(item.kind(), Some(TypeKind::Enum));
+        assert_eq!(
+            item.render().unwrap(),
+            "enum Status {\n  active @0;\n  suspended @1;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_try_into_native_enum_leaves_mixed_union_unchanged() {
+        let mut union = Union::new();
+        union.add_variant(UnionVariant::new("none".to_string(), 0, CapnpType::Void));
+        union.add_variant(UnionVariant::new("some".to_string(), 1, CapnpType::Int32));
+        let mut value = Struct::new("MaybeInt".to_string());
+        value.set_union(union);
+        let item = SchemaItem::Struct(value);
+
+        assert_eq!(item.clone().try_into_native_enum(), Err(item));
+    }
+
+    /// A document using every construct the renderer supports
+    fn demo_schema() -> Schema {
+        let mut address = Struct::new("Address".to_string());
+        address.add_field(Field::new("city".to_string(), 0, CapnpType::Text));
+        let mut zip = Field::new("zip".to_string(), 1, CapnpType::UInt32);
+        zip.set_default(DefaultValue::Int(10115));
+        address.add_field(zip);
+
+        let mut person = Struct::new("Person".to_string());
+        person.add_nested(SchemaItem::Struct(address));
+        let mut name = Field::new("name".to_string(), 1, CapnpType::Text);
+        name.set_rust_name("full_name".to_string());
+        person.add_field(name);
+        person.add_field(Field::new("id".to_string(), 0, CapnpType::UInt64));
+        let mut fax = Field::new("fax".to_string(), 2, CapnpType::Text);
+        fax.set_deprecated(true);
+        person.add_field(fax);
+        person.add_field(Field::new(
+            "home".to_string(),
+            3,
+            CapnpType::Option(Box::new(CapnpType::UserDefined("Address".to_string()))),
+        ));
+        person.add_group(Group::new(
+            "position".to_string(),
+            vec![
+                Field::new("x".to_string(), 5, CapnpType::Float32),
+                Field::new("y".to_string(), 4, CapnpType::Float32),
+            ],
+        ));
+        person.add_extra_field("oldId @6 :UInt64".to_string());
+        let mut status = Union::new();
+        status.add_variant(UnionVariant::new("active".to_string(), 7, CapnpType::Void));
+        status.add_variant(UnionVariant::new_group(
+            "banned".to_string(),
+            vec![Field::new(
+                "reason".to_string(),
+                8,
+                CapnpType::Result(Box::new(CapnpType::Text), Box::new(CapnpType::Int32)),
+            )],
+        ));
+        status.add_variant(UnionVariant::new_group(
+            "away".to_string(),
+            vec![Field::new(
+                "until".to_string(),
+                9,
+                CapnpType::List(Box::new(CapnpType::UserDefined("Stamp".to_string()))),
+            )],
+        ));
+        person.set_union(status);
+        person.add_raw_line("# end of person".to_string());
+
+        let mut color = Enum::new("Color".to_string());
+        color.add_enumerant("red".to_string(), 1);
+        color.add_enumerant("green".to_string(), 0);
+
+        let mut directory = Interface::new("Directory".to_string());
+        let mut lookup = Method::new("lookup".to_string(), 0);
+        lookup.add_param("id".to_string(), CapnpType::UInt64);
+        lookup.add_result(
+            "person".to_string(),
+            CapnpType::UserDefined("Person".to_string()),
+        );
+        directory.add_method(lookup);
+
+        let mut doc = Schema::new();
+        doc.add_item(SchemaItem::Struct(person));
+        doc.add_item(SchemaItem::Enum(color));
+        doc.add_item(SchemaItem::Interface(directory));
+        doc.add_item(SchemaItem::Transparent(Transparent {
+            name: "Stamp".to_string(),
+            target: CapnpType::UInt64,
+        }));
+        doc
+    }
+
+    #[test]
+    fn test_render_demo_schema() {
+        let doc = demo_schema();
+        let options = RenderOptions {
+            rust_name_comments: true,
+            blank_lines_between_groups: true,
+            ..RenderOptions::default()
+        };
+
+        assert_eq!(
+            doc.render_with(&options).unwrap(),
+            "struct Person {\n  struct Address {\n    city @0 :Text;\n    zip @1 :UInt32 = 10115;\n  }\n  id @0 :UInt64;\n  name @1 :Text;  # rust: full_name\n  fax @2 :Text;  # deprecated\n  home @3 :OptionPersonAddress;\n  position :group {\n    y @4 :Float32;\n    x @5 :Float32;\n  }\n  oldId @6 :UInt64;\n  union {\n    active @7 :Void;\n    banned :group {\n      reason @8 :ResultTextInt32;\n    }\n\n    away :group {\n      until @9 :List(UInt64);\n    }\n  }\n  # end of person\n}\n\n\
+             enum Color {\n  green @0;\n  red @1;\n}\n\n\
+             interface Directory {\n  lookup @0 (id :UInt64) -> (person :Person);\n}\n\n\
+             struct OptionPersonAddress {\n  union {\n    none @0 :Void;\n    some @1 :Person.Address;\n  }\n}\n\n\
+             struct ResultTextInt32 {\n  union {\n    ok @0 :Text;\n    err @1 :Int32;\n  }\n}\n"
+        );
+    }
 }
@@ -10,19 +10,43 @@ use std::path::PathBuf;
 use std::sync::LazyLock;
 use std::sync::Mutex;
 use syn::{
-    Attribute, Data, DeriveInput, Error, Fields, FieldsNamed, FieldsUnnamed, Lit, LitInt, LitStr,
+    Attribute, Data, DeriveInput, Error, Fields, FieldsNamed, FieldsUnnamed, LitInt, LitStr,
     Result, Token, parse_macro_input,
 };
 
-// Global state to track schema files and their content
-static SCHEMA_FILES: LazyLock<Mutex<HashMap<String, (u64, Vec<capnp_model::SchemaItem>)>>> =
+/// Accumulated state for a single schema file: its file ID and the items recorded so far
+type SchemaFileState = (u64, Vec<capnp_model::SchemaItem>);
+
+// Global state to track schema files and their content, keyed by the schema file's
+// path so that crates using the same file name don't share state.
+//
+// Expansion order isn't guaranteed, and tools like rust-analyzer may re-expand single
+// macros in a long-lived process, so the state only ever converges: re-running
+// `capnp_schema_file!` keeps the items recorded so far, and re-running a derive
+// replaces that type's item instead of adding a second copy.
+static SCHEMA_FILES: LazyLock<Mutex<HashMap<String, SchemaFileState>>> =
     LazyLock::new(|| Mutex::new(HashMap::new()));
 
 /// Creates a new capnp schema file and initializes it with the file ID
 #[proc_macro]
 pub fn capnp_schema_file(input: TokenStream) -> TokenStream {
+    let (filename, file_id) = match parse_schema_file_args(input.into()) {
+        Ok(args) => args,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    init_schema_file(&filename, file_id);
+
+    // The macro expands to nothing visible in the code
+    quote!().into()
+}
+
+/// Parses the file name and ID passed to `capnp_schema_file!`
+///
+/// The ID can be written in any integer literal form, e.g. hex as printed by `capnp id`.
+fn parse_schema_file_args(input: proc_macro2::TokenStream) -> Result<(String, u64)> {
     let parser = syn::punctuated::Punctuated::<syn::Expr, syn::Token![,]>::parse_terminated;
-    let input = parse_macro_input!(input with parser);
+    let input = syn::parse::Parser::parse2(parser, input)?;
     let mut iter = input.into_iter();
 
     let filename = match iter.next() {
@@ -31,9 +55,10 @@ pub fn capnp_schema_file(input: TokenStream) -> TokenStream {
             ..
         })) => s.value(),
         _ => {
-            return syn::Error::new(Span::call_site(), "First argument must be a string literal")
-                .to_compile_error()
-                .into();
+            return Err(Error::new(
+                Span::call_site(),
+                "First argument must be a string literal",
+            ));
         }
     };
 
@@ -41,30 +66,21 @@ pub fn capnp_schema_file(input: TokenStream) -> TokenStream {
         Some(syn::Expr::Lit(syn::ExprLit {
             lit: syn::Lit::Int(i),
             ..
-        })) => match i.base10_parse::<u64>() {
-            Ok(id) => id,
-            Err(_) => {
-                return syn::Error::new(Span::call_site(), "Second argument must be a valid u64")
-                    .to_compile_error()
-                    .into();
-            }
-        },
+        })) => i
+            .base10_parse::<u64>()
+            .map_err(|_| Error::new(Span::call_site(), "Second argument must be a valid u64"))?,
         _ => {
-            return syn::Error::new(
+            return Err(Error::new(
                 Span::call_site(),
                 "Second argument must be an integer literal",
-            )
-            .to_compile_error()
-            .into();
+            ));
         }
     };
 
-    // Initialize the schema file in our global state
-    let mut files = SCHEMA_FILES.lock().unwrap();
-    files.insert(filename.clone(), (file_id, Vec::new()));
+    capnp_model::check_file_id(file_id)
+        .map_err(|err| Error::new(Span::call_site(), err.to_string()))?;
 
-    // The macro expands to nothing visible in the code
-    quote!().into()
+    Ok((filename, file_id))
 }
 
 /// Main derive macro for CapnpType - now also appends to schema files
@@ -73,15 +89,7 @@ pub fn derive_capnp_type(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
     match generate_capnp_type(&input) {
-        Ok(tokens) => {
-            // Check if this type should be added to a schema file
-            if let Ok(filename) = extract_schema_filename(&input.attrs) {
-                if let Err(e) = record_schema_item(&input, &filename) {
-                    return e.to_compile_error().into();
-                }
-            }
-            tokens.into()
-        }
+        Ok(tokens) => tokens.into(),
         Err(err) => err.to_compile_error().into(),
     }
 }
@@ -126,18 +134,21 @@ pub fn complete_capnp_schema(input: TokenStream) -> TokenStream {
         }
     }
 
-    // Collect the rest as the module declaration
-    let module_decl_tokens: proc_macro2::TokenStream = tokens.collect();
+    // Collect the rest as the module declaration, followed by any compiler options
+    let (module_decl_tokens, compile_options) = match parse_compile_options(tokens.collect()) {
+        Ok(parsed) => parsed,
+        Err(err) => return err.to_compile_error().into(),
+    };
 
     // Get the accumulated schema content and write it all at once
     let (file_id, schema_items) = {
         let files = SCHEMA_FILES.lock().unwrap();
-        match files.get(&filename) {
+        match files.get(&schema_file_key(&filename)) {
             Some((file_id, items)) => (*file_id, items.clone()),
             None => {
                 return syn::Error::new(
                     Span::call_site(),
-                    &format!(
+                    format!(
                         "No schema file '{}' found. Did you call capnp_schema_file! first?",
                         filename
                     ),
@@ -149,52 +160,49 @@ pub fn complete_capnp_schema(input: TokenStream) -> TokenStream {
     };
 
     // Write the schema to the manifest directory
-    let manifest_dir =
-        env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR environment variable not set");
-    let manifest_dir = PathBuf::from(manifest_dir);
+    let manifest_dir = manifest_dir();
     let schema_path = manifest_dir.join(&filename);
 
-    // Create the complete schema
-    let mut schema = capnp_model::Schema::new();
-    for item in &schema_items {
-        schema.add_item(item.clone());
-    }
-
     // Render and write the schema
-    let schema_content = match schema.render() {
-        Ok(content) => content,
-        Err(e) => {
-            return syn::Error::new(
-                Span::call_site(),
-                &format!("Failed to render schema: {}", e),
-            )
-            .to_compile_error()
-            .into();
-        }
-    };
-
-    let full_content = format!("@0x{:x};\n\n{}", file_id, schema_content);
+    let full_content =
+        match render_schema_file(file_id, schema_items, compile_options.allow_undefined_types) {
+            Ok(content) => content,
+            Err(e) => {
+                return syn::Error::new(
+                    Span::call_site(),
+                    format!("Failed to render schema: {}", e),
+                )
+                .to_compile_error()
+                .into();
+            }
+        };
 
     if let Err(e) = fs::write(&schema_path, full_content) {
         return syn::Error::new(
             Span::call_site(),
-            &format!("Failed to write schema file: {}", e),
+            format!("Failed to write schema file: {}", e),
         )
         .to_compile_error()
         .into();
     }
 
-    let target_dir = TARGET_DIR.join("code-first-capnp");
+    let target_dir = match &compile_options.output_path {
+        Some(output_path) => manifest_dir.join(output_path),
+        None => TARGET_DIR.join("code-first-capnp"),
+    };
     // Use capnpc to compile the schema
-    if let Err(e) = capnpc::CompilerCommand::new()
+    let mut command = capnpc::CompilerCommand::new();
+    command
         .src_prefix(&manifest_dir)
         .file(&schema_path)
-        .output_path(&target_dir)
-        .run()
-    {
+        .output_path(&target_dir);
+    for import_path in &compile_options.import_paths {
+        command.import_path(manifest_dir.join(import_path));
+    }
+    if let Err(e) = command.run() {
         return syn::Error::new(
             Span::call_site(),
-            &format!("Failed to compile schema with capnpc: {}", e),
+            format!("Failed to compile schema with capnpc: {}", e),
         )
         .to_compile_error()
         .into();
@@ -211,18 +219,188 @@ pub fn complete_capnp_schema(input: TokenStream) -> TokenStream {
     .into()
 }
 
-fn record_schema_item(input: &DeriveInput, filename: &str) -> Result<()> {
-    // Generate schema item using the capnp_model
-    let schema_item = generate_schema_item_with_model(input)?;
+/// Options for `capnpc` given after the module in `complete_capnp_schema!`, with
+/// paths relative to the manifest directory
+#[derive(Debug, Default, PartialEq)]
+struct CompileOptions {
+    /// `import_paths = ["..."]`: directories searched by `import` statements
+    import_paths: Vec<String>,
+    /// `output_path = "..."`: where the generated Rust code is written
+    output_path: Option<String>,
+    /// `allow_undefined_types = true`: allows references to types the file doesn't
+    /// define, e.g. ones from imported files
+    allow_undefined_types: bool,
+}
+
+/// Splits the arguments after the filename into the module declaration and the
+/// `key = value` compiler options that may follow it, separated by commas
+fn parse_compile_options(
+    tokens: proc_macro2::TokenStream,
+) -> Result<(proc_macro2::TokenStream, CompileOptions)> {
+    let mut segments = vec![proc_macro2::TokenStream::new()];
+    for token in tokens {
+        match &token {
+            proc_macro2::TokenTree::Punct(punct) if punct.as_char() == ',' => {
+                segments.push(proc_macro2::TokenStream::new())
+            }
+            _ => segments.last_mut().unwrap().extend([token]),
+        }
+    }
+
+    let mut segments = segments.into_iter();
+    let module_decl = segments.next().unwrap();
+    let mut options = CompileOptions::default();
+    for segment in segments.filter(|segment| !segment.is_empty()) {
+        let option: syn::MetaNameValue = syn::parse2(segment)?;
+        if option.path.is_ident("import_paths") {
+            let syn::Expr::Array(paths) = &option.value else {
+                return Err(Error::new_spanned(
+                    &option.value,
+                    "import_paths must be an array of string literals",
+                ));
+            };
+            for path in &paths.elems {
+                match path {
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(path),
+                        ..
+                    }) => options.import_paths.push(path.value()),
+                    _ => {
+                        return Err(Error::new_spanned(
+                            path,
+                            "import_paths must be an array of string literals",
+                        ));
+                    }
+                }
+            }
+        } else if option.path.is_ident("output_path") {
+            match &option.value {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(path),
+                    ..
+                }) => options.output_path = Some(path.value()),
+                _ => {
+                    return Err(Error::new_spanned(
+                        &option.value,
+                        "output_path must be a string literal",
+                    ));
+                }
+            }
+        } else if option.path.is_ident("allow_undefined_types") {
+            match &option.value {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Bool(allow),
+                    ..
+                }) => options.allow_undefined_types = allow.value,
+                _ => {
+                    return Err(Error::new_spanned(
+                        &option.value,
+                        "allow_undefined_types must be a bool literal",
+                    ));
+                }
+            }
+        } else {
+            return Err(Error::new_spanned(
+                &option.path,
+                "Unknown option, expected import_paths, output_path or allow_undefined_types",
+            ));
+        }
+    }
+
+    Ok((module_decl, options))
+}
+
+/// Renders the complete contents of a schema file
+///
+/// Items are sorted by type name, so the output doesn't depend on the order in which
+/// derives were expanded. The file is validated as a whole first, so a reference to a
+/// type that was never recorded into it is an error rather than a file capnpc rejects,
+/// unless `allow_undefined_types` is set for files referencing imported types.
+fn render_schema_file(
+    file_id: u64,
+    mut schema_items: Vec<capnp_model::SchemaItem>,
+    allow_undefined_types: bool,
+) -> std::result::Result<String, capnp_model::ValidationError> {
+    schema_items.sort_by(|a, b| a.name().cmp(b.name()));
+
+    let mut schema = capnp_model::Schema::new();
+    schema.extend(schema_items);
+    schema.set_allow_undefined_types(allow_undefined_types);
+    schema.validate()?;
+
+    // Keep a single trailing newline when there's nothing after the file ID
+    let body = schema.render()?;
+    if body.is_empty() {
+        Ok(format!("{}\n", capnp_model::format_file_id(file_id)))
+    } else {
+        Ok(format!(
+            "{}\n\n{}",
+            capnp_model::format_file_id(file_id),
+            body
+        ))
+    }
+}
+
+fn manifest_dir() -> PathBuf {
+    let manifest_dir =
+        env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR environment variable not set");
+    PathBuf::from(manifest_dir)
+}
+
+/// The key of a schema file in `SCHEMA_FILES`: the path it's written to
+fn schema_file_key(filename: &str) -> String {
+    manifest_dir().join(filename).to_string_lossy().into_owned()
+}
+
+/// Registers a schema file, keeping any items already recorded for it
+fn init_schema_file(filename: &str, file_id: u64) {
+    let mut files = SCHEMA_FILES.lock().unwrap();
+    files
+        .entry(schema_file_key(filename))
+        .and_modify(|(id, _)| *id = file_id)
+        .or_insert_with(|| (file_id, Vec::new()));
+}
+
+/// Renders a schema file from the items recorded so far and writes it, creating its
+/// directory if needed
+fn write_schema_file(
+    filename: &str,
+    allow_undefined_types: bool,
+) -> std::result::Result<(), String> {
+    let (file_id, schema_items) = {
+        let files = SCHEMA_FILES.lock().unwrap();
+        let Some((file_id, items)) = files.get(&schema_file_key(filename)) else {
+            return Err(format!("Schema file '{}' not initialized", filename));
+        };
+        (*file_id, items.clone())
+    };
+
+    let content = render_schema_file(file_id, schema_items, allow_undefined_types)
+        .map_err(|err| format!("Failed to render schema: {}", err))?;
+    let schema_path = manifest_dir().join(filename);
+    if let Some(parent) = schema_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|err| format!("Failed to create schema directory: {}", err))?;
+    }
+    fs::write(&schema_path, content).map_err(|err| format!("Failed to write schema file: {}", err))
+}
 
+/// Records an item into a schema file, replacing an earlier item with the same name
+fn record_schema_item(schema_item: &capnp_model::SchemaItem, filename: &str) -> Result<()> {
     // Add to the global state
     let mut files = SCHEMA_FILES.lock().unwrap();
-    if let Some((_, items)) = files.get_mut(filename) {
-        items.push(schema_item);
+    if let Some((_, items)) = files.get_mut(&schema_file_key(filename)) {
+        match items
+            .iter_mut()
+            .find(|item| item.name() == schema_item.name())
+        {
+            Some(item) => *item = schema_item.clone(),
+            None => items.push(schema_item.clone()),
+        }
     } else {
         return Err(Error::new(
             Span::call_site(),
-            &format!(
+            format!(
                 "Schema file '{}' not initialized. Did you call capnp_schema_file! first?",
                 filename
             ),
@@ -232,23 +410,82 @@ fn record_schema_item(input: &DeriveInput, filename: &str) -> Result<()> {
     Ok(())
 }
 
-fn generate_schema_item_with_model(input: &DeriveInput) -> Result<capnp_model::SchemaItem> {
+fn generate_schema_item_with_model(
+    input: &DeriveInput,
+    attrs: &CapnpAttrs,
+    mapped: &mut MappedTypes,
+) -> Result<capnp_model::SchemaItem> {
     // Create the appropriate SchemaItem using capnp_model
+    if attrs.transparent {
+        let inner = transparent_inner_field(input)?;
+        let target = rust_type_to_capnp_model_type(&inner.ty, &extract_generic_params(input))?;
+        return Ok(capnp_model::SchemaItem::Transparent(
+            capnp_model::Transparent {
+                name: input.ident.to_string(),
+                target,
+            },
+        ));
+    }
+
     match &input.data {
-        Data::Struct(_) => generate_struct_schema_item(&input),
-        Data::Enum(_) => generate_enum_schema_item(&input),
+        Data::Struct(_) => generate_struct_schema_item(input, attrs, mapped),
+        Data::Enum(_) => generate_enum_schema_item(input, attrs, mapped),
         Data::Union(_) => Err(Error::new_spanned(input, "Union types are not supported")),
     }
 }
 
-fn generate_struct_schema_item(input: &DeriveInput) -> Result<capnp_model::SchemaItem> {
+/// Returns the single field of a `#[capnp(transparent)]` struct
+fn transparent_inner_field(input: &DeriveInput) -> Result<&syn::Field> {
+    let fields = match &input.data {
+        Data::Struct(data_struct) => &data_struct.fields,
+        _ => {
+            return Err(Error::new_spanned(
+                input,
+                "capnp(transparent) can only be used on structs",
+            ));
+        }
+    };
+
+    let mut iter = fields.iter();
+    match (iter.next(), iter.next()) {
+        (Some(field), None) => Ok(field),
+        _ => Err(Error::new_spanned(
+            input,
+            "capnp(transparent) requires a struct with exactly one field",
+        )),
+    }
+}
+
+fn generate_struct_schema_item(
+    input: &DeriveInput,
+    attrs: &CapnpAttrs,
+    mapped: &mut MappedTypes,
+) -> Result<capnp_model::SchemaItem> {
     let name = input.ident.to_string();
     let mut struct_def = capnp_model::Struct::new(name);
+    let generics = extract_generic_params(input);
+    for param in &generics.types {
+        struct_def.add_generic_param(param.clone());
+    }
 
+    let rename = RenameRule::from_attrs(input, attrs)?;
+    let mut groups = Vec::new();
     let fields = match &input.data {
         Data::Struct(data_struct) => match &data_struct.fields {
-            Fields::Named(fields) => generate_named_fields_for_model(fields)?,
-            Fields::Unnamed(fields) => generate_unnamed_fields_for_model(fields)?,
+            Fields::Named(fields) => generate_named_fields_for_model(
+                fields,
+                &generics,
+                Some(&mut groups),
+                rename,
+                mapped,
+            )?,
+            Fields::Unnamed(fields) => generate_unnamed_fields_for_model(
+                fields,
+                &generics,
+                Some(&mut groups),
+                &[],
+                mapped,
+            )?,
             Fields::Unit => Vec::new(),
         },
         _ => unreachable!(),
@@ -257,29 +494,55 @@ fn generate_struct_schema_item(input: &DeriveInput) -> Result<capnp_model::Schem
     for field in fields {
         struct_def.add_field(field);
     }
+    for group in groups {
+        struct_def.add_group(group);
+    }
 
-    // Add extra fields
-    let extra_fields = extract_extra_fields(&input.attrs)?;
-    for extra in extra_fields {
-        struct_def.add_extra_field(extra);
+    // Add extra fields and raw lines
+    for extra in &attrs.extra {
+        struct_def.add_extra_field(extra.clone());
+    }
+    for line in &attrs.raw {
+        struct_def.add_raw_line(line.clone());
     }
 
+    struct_def.set_allow_empty(attrs.allow_empty);
+    apply_type_id(&mut struct_def, input, attrs)?;
+
     Ok(capnp_model::SchemaItem::Struct(struct_def))
 }
 
-fn generate_enum_schema_item(input: &DeriveInput) -> Result<capnp_model::SchemaItem> {
+fn generate_enum_schema_item(
+    input: &DeriveInput,
+    attrs: &CapnpAttrs,
+    mapped: &mut MappedTypes,
+) -> Result<capnp_model::SchemaItem> {
     let name = input.ident.to_string();
     let mut struct_def = capnp_model::Struct::new(name);
     let mut union_def = capnp_model::Union::new();
+    let generics = extract_generic_params(input);
+    let rename = RenameRule::from_attrs(input, attrs)?;
+    for param in &generics.types {
+        struct_def.add_generic_param(param.clone());
+    }
 
-    let _variants = match &input.data {
+    match &input.data {
         Data::Enum(data_enum) => {
             for variant in &data_enum.variants {
                 let variant_name = variant.ident.to_string().to_lower_camel_case();
 
                 let union_variant = match &variant.fields {
                     Fields::Unit => {
-                        let variant_id = extract_capnp_id(&variant.attrs)?;
+                        // Unit variants require an ID on the variant itself, or failing
+                        // that an explicit discriminant
+                        let variant_attrs = parse_capnp_attrs(&variant.attrs)?;
+                        let variant_id = match variant_attrs.id {
+                            Some(id) => id,
+                            None => match discriminant_id(variant)? {
+                                Some(id) => id,
+                                None => required_id(&variant_attrs, variant)?,
+                            },
+                        };
                         capnp_model::UnionVariant::new(
                             variant_name,
                             variant_id,
@@ -287,11 +550,35 @@ fn generate_enum_schema_item(input: &DeriveInput) -> Result<capnp_model::SchemaI
                         )
                     }
                     Fields::Unnamed(fields) => {
-                        let group_fields = generate_unnamed_fields_for_model(fields)?;
+                        // Data-bearing variants become groups - no variant ID needed, but
+                        // `ids = [...]` may number the elements positionally
+                        let variant_attrs = parse_capnp_attrs(&variant.attrs)?;
+                        if !variant_attrs.ids.is_empty()
+                            && variant_attrs.ids.len() != fields.unnamed.len()
+                        {
+                            return Err(Error::new_spanned(
+                                variant,
+                                format!(
+                                    "capnp(ids) needs one entry per tuple element ({} expected, \
+                                     {} given)",
+                                    fields.unnamed.len(),
+                                    variant_attrs.ids.len()
+                                ),
+                            ));
+                        }
+                        let group_fields = generate_unnamed_fields_for_model(
+                            fields,
+                            &generics,
+                            None,
+                            &variant_attrs.ids,
+                            mapped,
+                        )?;
                         capnp_model::UnionVariant::new_group(variant_name, group_fields)
                     }
                     Fields::Named(fields) => {
-                        let group_fields = generate_named_fields_for_model(fields)?;
+                        let group_fields = generate_named_fields_for_model(
+                            fields, &generics, None, rename, mapped,
+                        )?;
                         capnp_model::UnionVariant::new_group(variant_name, group_fields)
                     }
                 };
@@ -300,56 +587,522 @@ fn generate_enum_schema_item(input: &DeriveInput) -> Result<capnp_model::SchemaI
             }
         }
         _ => unreachable!(),
-    };
+    }
 
+    if let Some(union_name) = &attrs.union_name {
+        union_def.set_name(union_name.clone());
+    }
+    if attrs.tagged {
+        add_tag_field(&mut struct_def, &union_def, input)?;
+    }
     struct_def.set_union(union_def);
 
-    // Add extra fields
-    let extra_fields = extract_extra_fields(&input.attrs)?;
-    for extra in extra_fields {
-        struct_def.add_extra_field(extra);
+    // Add extra fields and raw lines
+    for extra in &attrs.extra {
+        struct_def.add_extra_field(extra.clone());
+    }
+    for line in &attrs.raw {
+        struct_def.add_raw_line(line.clone());
     }
 
+    struct_def.set_allow_empty(attrs.allow_empty);
+    struct_def.set_is_enum(true);
+    apply_type_id(&mut struct_def, input, attrs)?;
+
     Ok(capnp_model::SchemaItem::Struct(struct_def))
 }
 
-fn generate_named_fields_for_model(fields: &FieldsNamed) -> Result<Vec<capnp_model::Field>> {
+/// Adds the informational `tag @0 :UInt16` field of a `#[capnp(tagged)]` enum, which
+/// reserves ordinal 0 so the union's ordinals must start at 1
+fn add_tag_field(
+    struct_def: &mut capnp_model::Struct,
+    union_def: &capnp_model::Union,
+    input: &DeriveInput,
+) -> Result<()> {
+    let takes_tag_id = union_def
+        .variants
+        .iter()
+        .any(|variant| match &variant.variant_inner {
+            capnp_model::UnionVariantInner::Type { id, .. } => *id == 0,
+            capnp_model::UnionVariantInner::Group(fields) => {
+                fields.iter().any(|field| field.id == 0)
+            }
+        });
+    if takes_tag_id {
+        return Err(Error::new_spanned(
+            input,
+            "capnp(tagged) reserves @0 for the tag field; number the variants from @1",
+        ));
+    }
+
+    struct_def.add_field(capnp_model::Field::new(
+        "tag".to_string(),
+        0,
+        capnp_model::CapnpType::UInt16,
+    ));
+    Ok(())
+}
+
+/// Pins the struct's type ID from `#[capnp(type_id = 0x...)]`
+fn apply_type_id(
+    struct_def: &mut capnp_model::Struct,
+    input: &DeriveInput,
+    attrs: &CapnpAttrs,
+) -> Result<()> {
+    if let Some(type_id) = attrs.type_id {
+        if type_id & (1 << 63) == 0 {
+            return Err(Error::new_spanned(
+                input,
+                format!(
+                    "capnp(type_id) {:#x} must have its high bit set, as Cap'n Proto requires",
+                    type_id
+                ),
+            ));
+        }
+        struct_def.set_type_id(type_id);
+    }
+    Ok(())
+}
+
+/// The default transform from Rust field names to Cap'n Proto names, chosen with
+/// `#[capnp(rename_all = "...")]`
+#[derive(Clone, Copy, Default)]
+enum RenameRule {
+    /// `camelCase`: `user_id` becomes `userId`
+    #[default]
+    CamelCase,
+    /// `none`: names are kept verbatim, so fields containing underscores need a `name`
+    None,
+}
+
+impl RenameRule {
+    /// Values accepted by `#[capnp(rename_all = "...")]`
+    const NAMES: &[&str] = &["none", "camelCase"];
+
+    fn from_attrs(input: &DeriveInput, attrs: &CapnpAttrs) -> Result<Self> {
+        match attrs.rename_all.as_deref() {
+            None | Some("camelCase") => Ok(RenameRule::CamelCase),
+            Some("none") => Ok(RenameRule::None),
+            Some("snake_case") => Err(Error::new_spanned(
+                input,
+                "rename_all = \"snake_case\" produces names with underscores, which capnpc rejects",
+            )),
+            Some(other) => Err(Error::new_spanned(
+                input,
+                format!(
+                    "Unknown rename_all '{}', expected one of: {}",
+                    other,
+                    Self::NAMES.join(", ")
+                ),
+            )),
+        }
+    }
+
+    fn apply(self, field_name: &str) -> String {
+        match self {
+            RenameRule::CamelCase => field_name.to_lower_camel_case(),
+            RenameRule::None => field_name.to_string(),
+        }
+    }
+}
+
+/// Generates the fields of a struct or group
+///
+/// Fields marked `#[capnp(group)]` are collected into `groups`, which is `None` where
+/// groups aren't supported (inside union variants). Field names are transformed with
+/// `rename` unless they have a `name` attribute.
+fn generate_named_fields_for_model(
+    fields: &FieldsNamed,
+    generics: &GenericParams,
+    mut groups: Option<&mut Vec<capnp_model::Group>>,
+    rename: RenameRule,
+    mapped: &mut MappedTypes,
+) -> Result<Vec<capnp_model::Field>> {
     let mut result = Vec::new();
 
     for field in &fields.named {
+        let attrs = parse_capnp_attrs(&field.attrs)?;
         let field_name = field.ident.as_ref().unwrap().to_string();
-        let field_id = extract_capnp_id(&field.attrs)?;
-        let custom_name = extract_custom_name(&field.attrs)?;
-        let capnp_name = custom_name.unwrap_or_else(|| field_name.to_lower_camel_case());
-        let field_type = rust_type_to_capnp_model_type(&field.ty)?;
+        let capnp_name = match &attrs.name {
+            Some(name) => name.clone(),
+            None => {
+                let renamed = rename.apply(&field_name);
+                if renamed.contains('_') {
+                    return Err(Error::new_spanned(
+                        field,
+                        format!(
+                            "Field name '{}' is not a valid Cap'n Proto name; set one with #[capnp(name = \"...\")]",
+                            renamed
+                        ),
+                    ));
+                }
+                renamed
+            }
+        };
+        if attrs.group {
+            let group = generate_tuple_group(capnp_name, field, &attrs, generics)?;
+            push_group(&mut groups, group, field)?;
+            continue;
+        }
+        let field_id = required_id(&attrs, field)?;
+        let field_type = field_capnp_type(field, &attrs, generics)?;
+        if attrs.mapping {
+            mapped.insert(field_id, MappedType::new(&field.ty));
+        }
+        let time_unit = field_time_unit(field, &attrs)?;
+
+        let mut model_field = capnp_model::Field::new(capnp_name.clone(), field_id, field_type);
+        if capnp_name != field_name {
+            model_field.set_rust_name(field_name);
+        }
+        apply_default(&mut model_field, field, &attrs)?;
+        model_field.set_deprecated(attrs.deprecated);
+        if let Some(time_unit) = time_unit {
+            model_field.set_time_unit(time_unit);
+        }
 
-        result.push(capnp_model::Field::new(capnp_name, field_id, field_type));
+        // Fields marked `group = "name"` are collected into that named group
+        if let Some(group_name) = &attrs.group_name {
+            push_to_named_group(&mut groups, group_name, model_field, field)?;
+            continue;
+        }
+        result.push(model_field);
     }
 
     Ok(result)
 }
 
-fn generate_unnamed_fields_for_model(fields: &FieldsUnnamed) -> Result<Vec<capnp_model::Field>> {
+fn generate_unnamed_fields_for_model(
+    fields: &FieldsUnnamed,
+    generics: &GenericParams,
+    mut groups: Option<&mut Vec<capnp_model::Group>>,
+    positional_ids: &[u32],
+    mapped: &mut MappedTypes,
+) -> Result<Vec<capnp_model::Field>> {
     let mut result = Vec::new();
 
     for (index, field) in fields.unnamed.iter().enumerate() {
+        let attrs = parse_capnp_attrs(&field.attrs)?;
         let field_name = format!("field{}", index);
-        let field_id = extract_capnp_id(&field.attrs)?;
-        let field_type = rust_type_to_capnp_model_type(&field.ty)?;
+        if attrs.group {
+            let group = generate_tuple_group(field_name, field, &attrs, generics)?;
+            push_group(&mut groups, group, field)?;
+            continue;
+        }
+        let field_id = match positional_ids.get(index) {
+            Some(_) if attrs.id.is_some() => {
+                return Err(Error::new_spanned(
+                    field,
+                    "Tuple element has its own capnp(id) as well as one from the variant's \
+                     capnp(ids)",
+                ));
+            }
+            Some(id) => *id,
+            None => required_id(&attrs, field)?,
+        };
+        let field_type = field_capnp_type(field, &attrs, generics)?;
+        if attrs.mapping {
+            mapped.insert(field_id, MappedType::new(&field.ty));
+        }
+        let time_unit = field_time_unit(field, &attrs)?;
+
+        let mut model_field = capnp_model::Field::new(field_name, field_id, field_type);
+        apply_default(&mut model_field, field, &attrs)?;
+        model_field.set_deprecated(attrs.deprecated);
+        if let Some(time_unit) = time_unit {
+            model_field.set_time_unit(time_unit);
+        }
 
-        result.push(capnp_model::Field::new(field_name, field_id, field_type));
+        if let Some(group_name) = &attrs.group_name {
+            push_to_named_group(&mut groups, group_name, model_field, field)?;
+            continue;
+        }
+        result.push(model_field);
     }
 
     Ok(result)
 }
 
-fn rust_type_to_capnp_model_type(ty: &syn::Type) -> Result<capnp_model::CapnpType> {
+/// The types of a type's `#[capnp(mapping)]` fields, by field ID
+///
+/// A mapped field's Cap'n Proto type comes from its `CapnpTypeMapping` implementation,
+/// which is only known at runtime. The model holds `Void` for it, and
+/// `get_capnp_schema()` calls the mapping in its place.
+type MappedTypes = HashMap<u32, MappedType>;
+
+/// The type of a `#[capnp(mapping)]` field: the type implementing `CapnpTypeMapping`,
+/// inside any containers the derive maps itself
+///
+/// Implementing the trait for `Vec<Decimal>` or `Option<Decimal>` isn't allowed outside
+/// this crate, so only the innermost type needs an implementation.
+#[derive(Debug, PartialEq)]
+enum MappedType {
+    Leaf(Box<syn::Type>),
+    List(Box<MappedType>),
+    Option(Box<MappedType>),
+}
+
+impl MappedType {
+    /// Finds the mapped type inside `Vec`, `Option`, `Box`, arrays, slices and
+    /// references
+    fn new(ty: &syn::Type) -> Self {
+        match ty {
+            syn::Type::Path(type_path) => {
+                if let Some(segment) = type_path.path.segments.last()
+                    && let syn::PathArguments::AngleBracketed(args) = &segment.arguments
+                    && let Some(syn::GenericArgument::Type(inner)) = args.args.first()
+                {
+                    if segment.ident == "Vec" {
+                        return MappedType::List(Box::new(MappedType::new(inner)));
+                    }
+                    if segment.ident == "Option" {
+                        return MappedType::Option(Box::new(MappedType::new(inner)));
+                    }
+                    if segment.ident == "Box" {
+                        return MappedType::new(inner);
+                    }
+                }
+                MappedType::Leaf(Box::new(ty.clone()))
+            }
+            syn::Type::Array(array) => MappedType::List(Box::new(MappedType::new(&array.elem))),
+            syn::Type::Slice(slice) => MappedType::List(Box::new(MappedType::new(&slice.elem))),
+            syn::Type::Reference(reference) => MappedType::new(&reference.elem),
+            syn::Type::Paren(paren) => MappedType::new(&paren.elem),
+            _ => MappedType::Leaf(Box::new(ty.clone())),
+        }
+    }
+
+    /// An expression building the field's `CapnpType` at runtime
+    fn tokens(&self, crate_name: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        match self {
+            MappedType::Leaf(ty) => {
+                quote! { <#ty as #crate_name::CapnpTypeMapping>::capnp_type() }
+            }
+            MappedType::List(inner) => {
+                let inner = inner.tokens(crate_name);
+                quote! { #crate_name::CapnpType::List(Box::new(#inner)) }
+            }
+            MappedType::Option(inner) => {
+                let inner = inner.tokens(crate_name);
+                quote! { #crate_name::CapnpType::Option(Box::new(#inner)) }
+            }
+        }
+    }
+}
+
+/// Maps a field's type, honouring `#[capnp(mapping)]` and `#[capnp(as = ...)]`
+fn field_capnp_type(
+    field: &syn::Field,
+    attrs: &CapnpAttrs,
+    generics: &GenericParams,
+) -> Result<capnp_model::CapnpType> {
+    if let Some(capnp_type) = &attrs.capnp_type {
+        if attrs.mapping || attrs.as_type.is_some() {
+            return Err(Error::new_spanned(
+                field,
+                "capnp(type = \"...\") cannot be combined with capnp(as) or capnp(mapping)",
+            ));
+        }
+        return capnp_model::CapnpType::parse(capnp_type).ok_or_else(|| {
+            Error::new_spanned(
+                field,
+                format!("Unknown Cap'n Proto type '{}' in capnp(type)", capnp_type),
+            )
+        });
+    }
+
+    // The caller records the field's Rust type in its `MappedTypes`
+    if attrs.mapping {
+        if attrs.default.is_some() {
+            return Err(Error::new_spanned(
+                field,
+                "capnp(default) cannot be combined with capnp(mapping)",
+            ));
+        }
+        return Ok(capnp_model::CapnpType::Void);
+    }
+
+    let field_type = rust_type_to_capnp_model_type(&field.ty, generics)?;
+    apply_as_override(field_type, field, attrs)
+}
+
+/// Every field of a struct, or of all an enum's variants
+fn input_fields(input: &DeriveInput) -> Vec<&syn::Field> {
+    match &input.data {
+        Data::Struct(data_struct) => data_struct.fields.iter().collect(),
+        Data::Enum(data_enum) => data_enum
+            .variants
+            .iter()
+            .flat_map(|variant| &variant.fields)
+            .collect(),
+        Data::Union(_) => Vec::new(),
+    }
+}
+
+/// Applies a `#[capnp(as = "data" | "text")]` override to a byte-like field's type
+fn apply_as_override(
+    field_type: capnp_model::CapnpType,
+    field: &syn::Field,
+    attrs: &CapnpAttrs,
+) -> Result<capnp_model::CapnpType> {
+    use capnp_model::CapnpType;
+
+    let Some(as_type) = &attrs.as_type else {
+        return Ok(field_type);
+    };
+
+    let byte_like = match &field_type {
+        CapnpType::Text | CapnpType::Data => true,
+        CapnpType::List(inner) => **inner == CapnpType::UInt8,
+        _ => false,
+    };
+    if !byte_like {
+        return Err(Error::new_spanned(
+            &field.ty,
+            format!(
+                "capnp(as = \"{}\") can only be used on String or byte fields",
+                as_type
+            ),
+        ));
+    }
+
+    match as_type.as_str() {
+        "data" => Ok(CapnpType::Data),
+        "text" => Ok(CapnpType::Text),
+        _ => Err(Error::new_spanned(
+            field,
+            format!(
+                "Invalid capnp(as) value '{}', expected \"data\" or \"text\"",
+                as_type
+            ),
+        )),
+    }
+}
+
+/// Sets the field's `#[capnp(default = ...)]` value, checking that it fits the field's type
+fn apply_default(
+    model_field: &mut capnp_model::Field,
+    field: &syn::Field,
+    attrs: &CapnpAttrs,
+) -> Result<()> {
+    if let Some(default) = &attrs.default {
+        model_field.set_default(default.clone());
+        model_field
+            .validate()
+            .map_err(|err| Error::new_spanned(field, err.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Generates a named group from a tuple-typed field marked `#[capnp(group, ids = [...])]`
+///
+/// Each tuple element becomes a group member `fieldN`, taking its ID from `ids`.
+fn generate_tuple_group(
+    name: String,
+    field: &syn::Field,
+    attrs: &CapnpAttrs,
+    generics: &GenericParams,
+) -> Result<capnp_model::Group> {
+    let syn::Type::Tuple(tuple) = &field.ty else {
+        return Err(Error::new_spanned(
+            &field.ty,
+            "capnp(group) can only be used on tuple-typed fields",
+        ));
+    };
+    if attrs.ids.len() != tuple.elems.len() {
+        return Err(Error::new_spanned(
+            field,
+            format!(
+                "capnp(group) needs one entry in `ids` per tuple element ({} expected, {} given)",
+                tuple.elems.len(),
+                attrs.ids.len()
+            ),
+        ));
+    }
+
+    let mut fields = Vec::new();
+    for (index, (elem, id)) in tuple.elems.iter().zip(&attrs.ids).enumerate() {
+        let field_type = rust_type_to_capnp_model_type(elem, generics)?;
+        let mut model_field = capnp_model::Field::new(format!("field{}", index), *id, field_type);
+        if let Some(time_unit) = time_type_unit(elem) {
+            model_field.set_time_unit(time_unit.to_string());
+        }
+        fields.push(model_field);
+    }
+
+    Ok(capnp_model::Group::new(name, fields))
+}
+
+/// Adds a field marked `group = "name"` to that named group, creating it on first use
+fn push_to_named_group(
+    groups: &mut Option<&mut Vec<capnp_model::Group>>,
+    group_name: &str,
+    model_field: capnp_model::Field,
+    field: &syn::Field,
+) -> Result<()> {
+    let Some(groups) = groups.as_deref_mut() else {
+        return Err(Error::new_spanned(
+            field,
+            "capnp(group = \"...\") is only supported on struct fields",
+        ));
+    };
+    match groups.iter_mut().find(|group| group.name == group_name) {
+        Some(group) => group.fields.push(model_field),
+        None => groups.push(capnp_model::Group::new(
+            group_name.to_string(),
+            vec![model_field],
+        )),
+    }
+    Ok(())
+}
+
+fn push_group(
+    groups: &mut Option<&mut Vec<capnp_model::Group>>,
+    group: capnp_model::Group,
+    field: &syn::Field,
+) -> Result<()> {
+    match groups {
+        Some(groups) => {
+            groups.push(group);
+            Ok(())
+        }
+        None => Err(Error::new_spanned(
+            field,
+            "capnp(group) is only supported on struct fields",
+        )),
+    }
+}
+
+/// Whether a type mentions a generic parameter, which generated helper structs can't
+/// refer to
+fn contains_generic_param(capnp_type: &capnp_model::CapnpType) -> bool {
+    use capnp_model::CapnpType;
+
+    match capnp_type {
+        CapnpType::GenericParam(_) => true,
+        CapnpType::List(inner) | CapnpType::Option(inner) => contains_generic_param(inner),
+        CapnpType::Result(ok, err) => contains_generic_param(ok) || contains_generic_param(err),
+        _ => false,
+    }
+}
+
+fn rust_type_to_capnp_model_type(
+    ty: &syn::Type,
+    generics: &GenericParams,
+) -> Result<capnp_model::CapnpType> {
     match ty {
         syn::Type::Path(type_path) => {
             let path = &type_path.path;
 
+            // Handle generic parameters of the enclosing type
+            if let Some(ident) = path.get_ident()
+                && generics.types.iter().any(|param| ident == param)
+            {
+                return Ok(capnp_model::CapnpType::GenericParam(ident.to_string()));
+            }
+
             // Handle common types
-            if path.is_ident("String") {
+            if path.is_ident("String") || path.is_ident("str") {
                 return Ok(capnp_model::CapnpType::Text);
             }
             if path.is_ident("bool") {
@@ -388,16 +1141,115 @@ fn rust_type_to_capnp_model_type(ty: &syn::Type) -> Result<capnp_model::CapnpTyp
                 return Ok(capnp_model::CapnpType::Float64);
             }
 
-            // Handle Vec<T>
-            if let Some(segment) = path.segments.first() {
-                if segment.ident == "Vec" {
-                    if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
-                        if let Some(syn::GenericArgument::Type(inner_type)) = args.args.first() {
-                            let inner_capnp_type = rust_type_to_capnp_model_type(inner_type)?;
-                            return Ok(capnp_model::CapnpType::List(Box::new(inner_capnp_type)));
-                        }
-                    }
+            // Handle Duration and SystemTime as a UInt64 count of `time_unit`s
+            if default_time_unit(path).is_some() {
+                return Ok(capnp_model::CapnpType::UInt64);
+            }
+
+            // Handle IP addresses: a v4 address is its big-endian u32, a v6 address its 16 bytes
+            if let Some(segment) = path.segments.last() {
+                if segment.ident == "Ipv4Addr" {
+                    return Ok(capnp_model::CapnpType::UInt32);
+                }
+                if segment.ident == "Ipv6Addr" {
+                    return Ok(capnp_model::CapnpType::Data);
+                }
+            }
+
+            // Handle bytes::Bytes and bytes::BytesMut as Data
+            #[cfg(feature = "bytes")]
+            if let Some(segment) = path.segments.last()
+                && (segment.ident == "Bytes" || segment.ident == "BytesMut")
+            {
+                return Ok(capnp_model::CapnpType::Data);
+            }
+
+            // Handle NonZeroU32 and friends, and NonZero<T>, as the integer they wrap
+            if let Some(segment) = path.segments.last() {
+                const INTEGERS: [&str; 8] = ["u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64"];
+                if let Some(integer) = segment.ident.to_string().strip_prefix("NonZero")
+                    && INTEGERS.contains(&integer.to_lowercase().as_str())
+                {
+                    let integer = syn::Ident::new(&integer.to_lowercase(), segment.ident.span());
+                    return rust_type_to_capnp_model_type(&syn::parse_quote!(#integer), generics);
+                }
+                if segment.ident == "NonZero"
+                    && let syn::PathArguments::AngleBracketed(args) = &segment.arguments
+                    && let Some(syn::GenericArgument::Type(inner_type)) = args.args.first()
+                {
+                    return rust_type_to_capnp_model_type(inner_type, generics);
+                }
+            }
+
+            // Handle Vec<T>, with Vec<u8> as Data
+            if let Some(segment) = path.segments.first()
+                && segment.ident == "Vec"
+                && let syn::PathArguments::AngleBracketed(args) = &segment.arguments
+                && let Some(syn::GenericArgument::Type(inner_type)) = args.args.first()
+            {
+                let inner_capnp_type = rust_type_to_capnp_model_type(inner_type, generics)?;
+                if inner_capnp_type == capnp_model::CapnpType::UInt8 {
+                    return Ok(capnp_model::CapnpType::Data);
                 }
+                return Ok(capnp_model::CapnpType::List(Box::new(inner_capnp_type)));
+            }
+
+            // Handle Option<T>
+            if let Some(segment) = path.segments.last()
+                && segment.ident == "Option"
+                && let syn::PathArguments::AngleBracketed(args) = &segment.arguments
+                && let Some(syn::GenericArgument::Type(inner_type)) = args.args.first()
+            {
+                let inner_capnp_type = rust_type_to_capnp_model_type(inner_type, generics)?;
+                if contains_generic_param(&inner_capnp_type) {
+                    return Err(Error::new_spanned(
+                        ty,
+                        "Option of a generic parameter is not supported",
+                    ));
+                }
+                return Ok(capnp_model::CapnpType::Option(Box::new(inner_capnp_type)));
+            }
+
+            // Handle Result<T, E>
+            if let Some(segment) = path.segments.last()
+                && segment.ident == "Result"
+                && let syn::PathArguments::AngleBracketed(args) = &segment.arguments
+                && let [
+                    syn::GenericArgument::Type(ok_type),
+                    syn::GenericArgument::Type(err_type),
+                ] = args.args.iter().collect::<Vec<_>>()[..]
+            {
+                let ok_capnp_type = rust_type_to_capnp_model_type(ok_type, generics)?;
+                let err_capnp_type = rust_type_to_capnp_model_type(err_type, generics)?;
+                if contains_generic_param(&ok_capnp_type) || contains_generic_param(&err_capnp_type)
+                {
+                    return Err(Error::new_spanned(
+                        ty,
+                        "Result of a generic parameter is not supported",
+                    ));
+                }
+                return Ok(capnp_model::CapnpType::Result(
+                    Box::new(ok_capnp_type),
+                    Box::new(err_capnp_type),
+                ));
+            }
+
+            // Handle Cow<'a, T> like its borrowed type
+            if let Some(segment) = path.segments.last()
+                && segment.ident == "Cow"
+                && let syn::PathArguments::AngleBracketed(args) = &segment.arguments
+                && let Some(syn::GenericArgument::Type(inner_type)) = args.args.last()
+            {
+                return rust_type_to_capnp_model_type(inner_type, generics);
+            }
+
+            // Handle Box<T> transparently, so Box<str> is Text and Box<[T]> a List
+            if let Some(segment) = path.segments.first()
+                && segment.ident == "Box"
+                && let syn::PathArguments::AngleBracketed(args) = &segment.arguments
+                && let Some(syn::GenericArgument::Type(inner_type)) = args.args.first()
+            {
+                return rust_type_to_capnp_model_type(inner_type, generics);
             }
 
             // Handle user-defined types
@@ -410,17 +1262,94 @@ fn rust_type_to_capnp_model_type(ty: &syn::Type) -> Result<capnp_model::CapnpTyp
 
             Ok(capnp_model::CapnpType::UserDefined(type_name))
         }
-        _ => Err(Error::new_spanned(ty, "Unsupported type")),
-    }
-}
-
-fn generate_capnp_type(input: &DeriveInput) -> Result<proc_macro2::TokenStream> {
-    let name = &input.ident;
+        // Handle the unit type as Void
+        syn::Type::Tuple(tuple) if tuple.elems.is_empty() => Ok(capnp_model::CapnpType::Void),
+        // Handle references like the type they point to
+        syn::Type::Reference(reference) => rust_type_to_capnp_model_type(&reference.elem, generics),
+        // Handle slices like Vec<T>
+        syn::Type::Slice(slice) => {
+            let elem_type = rust_type_to_capnp_model_type(&slice.elem, generics)?;
+            if elem_type == capnp_model::CapnpType::UInt8 {
+                Ok(capnp_model::CapnpType::Data)
+            } else {
+                Ok(capnp_model::CapnpType::List(Box::new(elem_type)))
+            }
+        }
+        // Handle fixed-size arrays: bytes map to Data, anything else to a List
+        syn::Type::Array(array) => {
+            if let syn::Expr::Path(length) = &array.len
+                && let Some(ident) = length.path.get_ident()
+                && generics.consts.iter().any(|param| ident == param)
+            {
+                return Err(Error::new_spanned(
+                    &array.len,
+                    format!(
+                        "Array length `{}` is a const generic parameter, which can't be resolved \
+                         when the schema is generated; use a literal or named constant length",
+                        ident
+                    ),
+                ));
+            }
+
+            let elem_type = rust_type_to_capnp_model_type(&array.elem, generics)?;
+            if elem_type == capnp_model::CapnpType::UInt8 {
+                Ok(capnp_model::CapnpType::Data)
+            } else {
+                Ok(capnp_model::CapnpType::List(Box::new(elem_type)))
+            }
+        }
+        _ => Err(Error::new_spanned(ty, "Unsupported type")),
+    }
+}
+
+fn generate_capnp_type(input: &DeriveInput) -> Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let attrs = parse_capnp_attrs(&input.attrs)?;
+    let mut mapped = MappedTypes::new();
+    let schema_item = generate_schema_item_with_model(input, &attrs, &mut mapped)?;
+
+    // Catch clashing ordinals here rather than in capnpc, e.g. a unit variant and a
+    // data variant's member both using @0
+    schema_item
+        .validate()
+        .map_err(|err| Error::new_spanned(input, err.to_string()))?;
+
+    // Mapped field types are only known at runtime, so can't be rendered here
+    if (attrs.file.is_some() || attrs.embed_schema) && !mapped.is_empty() {
+        return Err(Error::new_spanned(
+            input,
+            "capnp(mapping) fields are resolved at runtime, so the type can't use \
+             capnp(file) or capnp(embed_schema)",
+        ));
+    }
+
+    // Check if this type should be added to a schema file. With a file ID, the type is
+    // the root of a single-file schema: the file is set up and written right away,
+    // without capnp_schema_file! or complete_capnp_schema!
+    match (&attrs.file, attrs.file_id) {
+        (Some(filename), Some(file_id)) => {
+            capnp_model::check_file_id(file_id)
+                .map_err(|err| Error::new_spanned(input, err.to_string()))?;
+            init_schema_file(filename, file_id);
+            record_schema_item(&schema_item, filename)?;
+            write_schema_file(filename, attrs.allow_undefined_types)
+                .map_err(|err| Error::new_spanned(input, err))?;
+        }
+        (Some(filename), None) => record_schema_item(&schema_item, filename)?,
+        (None, Some(_)) => {
+            return Err(Error::new_spanned(
+                input,
+                "capnp(file_id) requires capnp(file = \"...\")",
+            ));
+        }
+        (None, None) => {}
+    }
 
     // Determine the correct crate name to use -- this is really only to support unit tests in the
-    // code-first-capnp crate.
+    // code-first-capnp crate. Within that package, the crate refers to itself as
+    // `code_first_capnp` too, so the same path works in its unit tests and its doctests.
     let crate_name = match crate_name("code-first-capnp") {
-        Ok(FoundCrate::Itself) => quote!(crate),
+        Ok(FoundCrate::Itself) => quote!(code_first_capnp),
         Ok(FoundCrate::Name(name)) => {
             let ident = proc_macro2::Ident::new(&name, proc_macro2::Span::call_site());
             quote!(#ident)
@@ -428,404 +1357,675 @@ fn generate_capnp_type(input: &DeriveInput) -> Result<proc_macro2::TokenStream>
         Err(_) => quote!(code_first_capnp),
     };
 
-    let schema_item = match &input.data {
-        Data::Struct(_) => generate_struct_schema(&input, &crate_name)?,
-        Data::Enum(_) => generate_enum_schema(&input, &crate_name)?,
-        Data::Union(_) => {
-            return Err(Error::new_spanned(input, "Union types are not supported"));
-        }
+    // Optionally embed the rendered schema text, including any helper structs
+    let embedded_schema = if attrs.embed_schema {
+        let text = capnp_model::render_items(std::slice::from_ref(&schema_item))
+            .map_err(|err| Error::new_spanned(input, err.to_string()))?;
+        quote! { pub const CAPNP_SCHEMA: &str = #text; }
+    } else {
+        quote! {}
     };
 
-    Ok(quote! {
-        impl #name {
-            pub fn get_capnp_schema() -> #crate_name::SchemaItem {
-                #schema_item
+    // In strict mode, refer to `get_capnp_schema` on every user-defined field type, so
+    // types that don't derive `CapnpType` fail to compile here rather than in capnpc
+    let strict_check = if attrs.strict {
+        let user_types = strict_checked_types(input);
+        quote! {
+            #[doc(hidden)]
+            #[allow(dead_code)]
+            fn __capnp_strict_check() {
+                #(let _ = <#user_types>::get_capnp_schema;)*
             }
         }
-    })
-}
-
-fn generate_struct_schema(
-    input: &DeriveInput,
-    crate_name: &proc_macro2::TokenStream,
-) -> Result<proc_macro2::TokenStream> {
-    let name = &input.ident;
-    let type_name = name.to_string();
-
-    let fields = match &input.data {
-        Data::Struct(data_struct) => match &data_struct.fields {
-            Fields::Named(fields) => generate_named_fields(fields, crate_name)?,
-            Fields::Unnamed(fields) => generate_unnamed_fields(fields, crate_name)?,
-            Fields::Unit => Vec::new(),
-        },
-        _ => unreachable!(),
-    };
-
-    let extra_fields = extract_extra_fields(&input.attrs)?;
-
-    let fields_tokens = if fields.is_empty() {
-        quote! { vec![] }
     } else {
-        quote! { vec![#(#fields),*] }
+        quote! {}
     };
 
-    let extra_fields_tokens = if extra_fields.is_empty() {
-        quote! { vec![] }
+    // Register the type for `all_registered_schemas()`, which is a no-op unless the
+    // `registry` feature is enabled; generic types have no single schema to register
+    let registration = if input.generics.type_params().next().is_none()
+        && input.generics.const_params().next().is_none()
+    {
+        quote! { #crate_name::__register_capnp_type!(#name); }
     } else {
-        let extra_strs: Vec<_> = extra_fields.iter().collect();
-        quote! { vec![#(#extra_strs.to_string()),*] }
+        quote! {}
     };
 
+    let schema_item = schema_item_tokens(&schema_item, &mapped, &crate_name);
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
     Ok(quote! {
-        #crate_name::SchemaItem::Struct(
-            #crate_name::Struct {
-                name: #type_name.to_string(),
-                fields: #fields_tokens,
-                union: None,
-                extra_fields: #extra_fields_tokens,
+        #registration
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            #embedded_schema
+            #strict_check
+
+            pub fn get_capnp_schema() -> #crate_name::SchemaItem {
+                #schema_item
             }
-        )
+        }
     })
 }
 
-fn generate_enum_schema(
-    input: &DeriveInput,
-    crate_name: &proc_macro2::TokenStream,
-) -> Result<proc_macro2::TokenStream> {
-    let name = &input.ident;
-    let type_name = name.to_string();
+/// Collects the Rust types of every field that map to user-defined Cap'n Proto types
+fn strict_checked_types(input: &DeriveInput) -> Vec<syn::Type> {
+    let generics = extract_generic_params(input);
 
-    let variants = match &input.data {
-        Data::Enum(data_enum) => {
-            let mut variant_tokens = Vec::new();
+    // Mapped fields are checked by their `CapnpTypeMapping` call instead
+    let mut user_types = Vec::new();
+    for field in input_fields(input) {
+        if !parse_capnp_attrs(&field.attrs).is_ok_and(|attrs| attrs.mapping) {
+            collect_user_defined_types(&field.ty, &generics, &mut user_types);
+        }
+    }
+    user_types
+}
 
-            for variant in &data_enum.variants {
-                let variant_name = variant.ident.to_string();
+/// Finds the parts of a Rust type that map to `CapnpType::UserDefined`, looking through
+/// containers like `Vec`, `Option` and references
+fn collect_user_defined_types(
+    ty: &syn::Type,
+    generics: &GenericParams,
+    user_types: &mut Vec<syn::Type>,
+) {
+    match ty {
+        syn::Type::Path(type_path) => {
+            if let Ok(capnp_model::CapnpType::UserDefined(_)) =
+                rust_type_to_capnp_model_type(ty, generics)
+            {
+                user_types.push(ty.clone());
+                return;
+            }
+            if let Some(segment) = type_path.path.segments.last()
+                && let syn::PathArguments::AngleBracketed(args) = &segment.arguments
+            {
+                for arg in &args.args {
+                    if let syn::GenericArgument::Type(inner) = arg {
+                        collect_user_defined_types(inner, generics, user_types);
+                    }
+                }
+            }
+        }
+        syn::Type::Reference(reference) => {
+            collect_user_defined_types(&reference.elem, generics, user_types)
+        }
+        syn::Type::Slice(slice) => collect_user_defined_types(&slice.elem, generics, user_types),
+        syn::Type::Array(array) => collect_user_defined_types(&array.elem, generics, user_types),
+        syn::Type::Paren(paren) => collect_user_defined_types(&paren.elem, generics, user_types),
+        syn::Type::Tuple(tuple) => {
+            for elem in &tuple.elems {
+                collect_user_defined_types(elem, generics, user_types);
+            }
+        }
+        _ => {}
+    }
+}
 
-                let variant_inner = match &variant.fields {
-                    Fields::Unit => {
-                        // Unit variants require an ID on the variant itself
-                        let variant_id = extract_capnp_id(&variant.attrs)?;
-                        quote! {
-                            #crate_name::UnionVariantInner::Type {
-                                id: #variant_id,
-                                capnp_type: #crate_name::CapnpType::Void,
-                            }
-                        }
+// The functions below turn capnp_model values into expressions that rebuild them at
+// runtime, so `get_capnp_schema()` returns exactly what was recorded into schema files.
+
+fn schema_item_tokens(
+    item: &capnp_model::SchemaItem,
+    mapped: &MappedTypes,
+    crate_name: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    match item {
+        capnp_model::SchemaItem::Struct(struct_def) => {
+            let name = &struct_def.name;
+            let generic_params = &struct_def.generic_params;
+            let fields = fields_tokens(&struct_def.fields, mapped, crate_name);
+            let groups = struct_def.groups.iter().map(|group| {
+                let name = &group.name;
+                let fields = fields_tokens(&group.fields, mapped, crate_name);
+                quote! {
+                    #crate_name::Group {
+                        name: #name.to_string(),
+                        fields: #fields,
                     }
-                    Fields::Unnamed(fields) => {
-                        // Data-bearing variants become groups - no variant ID needed
-                        let group_fields = generate_unnamed_fields(fields, crate_name)?;
-                        quote! {
-                            #crate_name::UnionVariantInner::Group(
-                                vec![#(#group_fields),*]
-                            )
-                        }
+                }
+            });
+            let unions = struct_def.unions.iter().map(|union| {
+                let variants = union
+                    .variants
+                    .iter()
+                    .map(|variant| union_variant_tokens(variant, mapped, crate_name));
+                let union_name = match &union.name {
+                    Some(name) => quote! { Some(#name.to_string()) },
+                    None => quote! { None },
+                };
+                quote! {
+                    #crate_name::Union {
+                        name: #union_name,
+                        variants: vec![#(#variants),*],
                     }
-                    Fields::Named(fields) => {
-                        // Named fields - use Group - no variant ID needed
-                        let group_fields = generate_named_fields(fields, crate_name)?;
-                        quote! {
-                            #crate_name::UnionVariantInner::Group(
-                                vec![#(#group_fields),*]
-                            )
-                        }
+                }
+            });
+            let extra_fields = &struct_def.extra_fields;
+            let raw_lines = &struct_def.raw_lines;
+            let allow_empty = struct_def.allow_empty;
+            let is_enum = struct_def.is_enum;
+            let type_id = match struct_def.type_id {
+                Some(type_id) => quote! { Some(#type_id) },
+                None => quote! { None },
+            };
+
+            quote! {
+                #crate_name::SchemaItem::Struct(
+                    #crate_name::Struct {
+                        name: #name.to_string(),
+                        type_id: #type_id,
+                        generic_params: vec![#(#generic_params.to_string()),*],
+                        fields: #fields,
+                        groups: vec![#(#groups),*],
+                        unions: vec![#(#unions),*],
+                        extra_fields: vec![#(#extra_fields.to_string()),*],
+                        raw_lines: vec![#(#raw_lines.to_string()),*],
+                        nested: Vec::new(),
+                        allow_empty: #allow_empty,
+                        is_enum: #is_enum,
                     }
-                };
-                let variant_name_camel = variant_name.to_lower_camel_case();
-
-                variant_tokens.push(quote! {
-                    #crate_name::UnionVariant {
-                        name: #variant_name_camel.to_string(),
-                        variant_inner: #variant_inner,
+                )
+            }
+        }
+        capnp_model::SchemaItem::Enum(_) => {
+            unreachable!("CapnpType is never derived as a native enum")
+        }
+        capnp_model::SchemaItem::Interface(_) => {
+            unreachable!("CapnpType is never derived as an interface")
+        }
+        capnp_model::SchemaItem::Transparent(transparent) => {
+            let name = &transparent.name;
+            let target = capnp_type_tokens(&transparent.target, crate_name);
+
+            quote! {
+                #crate_name::SchemaItem::Transparent(
+                    #crate_name::Transparent {
+                        name: #name.to_string(),
+                        target: #target,
                     }
-                });
+                )
             }
-
-            variant_tokens
         }
-        _ => unreachable!(),
-    };
+    }
+}
 
-    let extra_fields = extract_extra_fields(&input.attrs)?;
-    let extra_fields_tokens = if extra_fields.is_empty() {
-        quote! { vec![] }
-    } else {
-        let extra_strs: Vec<_> = extra_fields.iter().collect();
-        quote! { vec![#(#extra_strs.to_string()),*] }
+fn union_variant_tokens(
+    variant: &capnp_model::UnionVariant,
+    mapped: &MappedTypes,
+    crate_name: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let name = &variant.name;
+    let variant_inner = match &variant.variant_inner {
+        capnp_model::UnionVariantInner::Type { id, capnp_type } => {
+            let capnp_type = capnp_type_tokens(capnp_type, crate_name);
+            quote! {
+                #crate_name::UnionVariantInner::Type {
+                    id: #id,
+                    capnp_type: #capnp_type,
+                }
+            }
+        }
+        capnp_model::UnionVariantInner::Group(fields) => {
+            let fields = fields_tokens(fields, mapped, crate_name);
+            quote! { #crate_name::UnionVariantInner::Group(#fields) }
+        }
     };
 
-    Ok(quote! {
-        #crate_name::SchemaItem::Struct(
-            #crate_name::Struct {
-                name: #type_name.to_string(),
-                fields: vec![],
-                union: Some(#crate_name::Union {
-                    variants: vec![#(#variants),*],
-                }),
-                extra_fields: #extra_fields_tokens,
-            }
-        )
-    })
+    quote! {
+        #crate_name::UnionVariant {
+            name: #name.to_string(),
+            variant_inner: #variant_inner,
+        }
+    }
 }
 
-fn generate_named_fields(
-    fields: &FieldsNamed,
+fn fields_tokens(
+    fields: &[capnp_model::Field],
+    mapped: &MappedTypes,
     crate_name: &proc_macro2::TokenStream,
-) -> Result<Vec<proc_macro2::TokenStream>> {
-    let mut field_tokens = Vec::new();
-
-    for field in &fields.named {
-        let field_name = field.ident.as_ref().unwrap().to_string();
-        let field_id = extract_capnp_id(&field.attrs)?;
-        let custom_name = extract_custom_name(&field.attrs)?;
-        let capnp_name = custom_name.unwrap_or_else(|| field_name.to_lower_camel_case());
-        let field_type = generate_capnp_type_tokens(&field.ty, crate_name)?;
-
-        field_tokens.push(quote! {
+) -> proc_macro2::TokenStream {
+    let fields = fields.iter().map(|field| {
+        let name = &field.name;
+        let id = field.id;
+        let field_type = match mapped.get(&field.id) {
+            Some(mapped_type) => mapped_type.tokens(crate_name),
+            None => capnp_type_tokens(&field.field_type, crate_name),
+        };
+        let default = match &field.default {
+            Some(default) => {
+                let default = default_value_tokens(default, crate_name);
+                quote! { Some(#default) }
+            }
+            None => quote! { None },
+        };
+        let deprecated = field.deprecated;
+        let time_unit = match &field.time_unit {
+            Some(time_unit) => quote! { Some(#time_unit.to_string()) },
+            None => quote! { None },
+        };
+        let rust_name = match &field.rust_name {
+            Some(rust_name) => quote! { Some(#rust_name.to_string()) },
+            None => quote! { None },
+        };
+        quote! {
             #crate_name::CapnpField {
-                name: #capnp_name.to_string(),
-                id: #field_id,
+                name: #name.to_string(),
+                id: #id,
                 field_type: #field_type,
+                default: #default,
+                rust_name: #rust_name,
+                deprecated: #deprecated,
+                time_unit: #time_unit,
             }
-        });
-    }
+        }
+    });
 
-    Ok(field_tokens)
+    quote! { vec![#(#fields),*] }
 }
 
-fn generate_unnamed_fields(
-    fields: &FieldsUnnamed,
+fn default_value_tokens(
+    default: &capnp_model::DefaultValue,
     crate_name: &proc_macro2::TokenStream,
-) -> Result<Vec<proc_macro2::TokenStream>> {
-    let mut field_tokens = Vec::new();
-
-    for (index, field) in fields.unnamed.iter().enumerate() {
-        let field_name = format!("field{}", index);
-        let field_id = extract_capnp_id(&field.attrs)?;
-        let field_type = generate_capnp_type_tokens(&field.ty, crate_name)?;
+) -> proc_macro2::TokenStream {
+    use capnp_model::DefaultValue;
+
+    match default {
+        DefaultValue::Int(value) => quote! { #crate_name::DefaultValue::Int(#value) },
+        DefaultValue::UInt(value) => quote! { #crate_name::DefaultValue::UInt(#value) },
+        DefaultValue::Float(value) => quote! { #crate_name::DefaultValue::Float(#value) },
+        DefaultValue::Bool(value) => quote! { #crate_name::DefaultValue::Bool(#value) },
+        DefaultValue::Text(value) => {
+            quote! { #crate_name::DefaultValue::Text(#value.to_string()) }
+        }
+    }
+}
 
-        field_tokens.push(quote! {
-            #crate_name::CapnpField {
-                name: #field_name.to_string(),
-                id: #field_id,
-                field_type: #field_type,
-            }
-        });
+fn capnp_type_tokens(
+    capnp_type: &capnp_model::CapnpType,
+    crate_name: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    use capnp_model::CapnpType;
+
+    match capnp_type {
+        CapnpType::Bool => quote! { #crate_name::CapnpType::Bool },
+        CapnpType::Int8 => quote! { #crate_name::CapnpType::Int8 },
+        CapnpType::Int16 => quote! { #crate_name::CapnpType::Int16 },
+        CapnpType::Int32 => quote! { #crate_name::CapnpType::Int32 },
+        CapnpType::Int64 => quote! { #crate_name::CapnpType::Int64 },
+        CapnpType::UInt8 => quote! { #crate_name::CapnpType::UInt8 },
+        CapnpType::UInt16 => quote! { #crate_name::CapnpType::UInt16 },
+        CapnpType::UInt32 => quote! { #crate_name::CapnpType::UInt32 },
+        CapnpType::UInt64 => quote! { #crate_name::CapnpType::UInt64 },
+        CapnpType::Float32 => quote! { #crate_name::CapnpType::Float32 },
+        CapnpType::Float64 => quote! { #crate_name::CapnpType::Float64 },
+        CapnpType::Text => quote! { #crate_name::CapnpType::Text },
+        CapnpType::Data => quote! { #crate_name::CapnpType::Data },
+        CapnpType::Void => quote! { #crate_name::CapnpType::Void },
+        CapnpType::List(inner) => {
+            let inner = capnp_type_tokens(inner, crate_name);
+            quote! { #crate_name::CapnpType::List(Box::new(#inner)) }
+        }
+        CapnpType::Option(inner) => {
+            let inner = capnp_type_tokens(inner, crate_name);
+            quote! { #crate_name::CapnpType::Option(Box::new(#inner)) }
+        }
+        CapnpType::Result(ok, err) => {
+            let ok = capnp_type_tokens(ok, crate_name);
+            let err = capnp_type_tokens(err, crate_name);
+            quote! { #crate_name::CapnpType::Result(Box::new(#ok), Box::new(#err)) }
+        }
+        CapnpType::UserDefined(name) => {
+            quote! { #crate_name::CapnpType::UserDefined(#name.to_string()) }
+        }
+        CapnpType::GenericParam(name) => {
+            quote! { #crate_name::CapnpType::GenericParam(#name.to_string()) }
+        }
     }
+}
 
-    Ok(field_tokens)
+/// Time units accepted by `#[capnp(time_unit = "...")]`
+const TIME_UNITS: &[&str] = &["secs", "millis", "micros", "nanos"];
+
+/// Returns the default unit of `Duration` and `SystemTime`, which are stored as
+/// `UInt64`, or `None` for any other type
+///
+/// A `Duration` is a count of `time_unit`s (nanoseconds by default) and a
+/// `SystemTime` is a count of `time_unit`s since the Unix epoch (milliseconds by
+/// default). Only `std::time`/`core::time` paths and bare imports count, so other
+/// crates' types like `chrono::Duration` aren't mistaken for them.
+fn default_time_unit(path: &syn::Path) -> Option<&'static str> {
+    let segments: Vec<String> = path
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect();
+    let name = match segments.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [name] | ["std" | "core", "time", name] => name,
+        _ => return None,
+    };
+    match name {
+        "Duration" => Some("nanos"),
+        "SystemTime" => Some("millis"),
+        _ => None,
+    }
 }
 
-fn generate_capnp_type_tokens(
-    ty: &syn::Type,
-    crate_name: &proc_macro2::TokenStream,
-) -> Result<proc_macro2::TokenStream> {
-    match ty {
-        syn::Type::Path(type_path) => {
-            let path = &type_path.path;
+/// Returns the default unit of a time type, looking through `Option`, `Vec` and `Box`
+fn time_type_unit(ty: &syn::Type) -> Option<&'static str> {
+    let syn::Type::Path(type_path) = ty else {
+        return None;
+    };
+    if let Some(unit) = default_time_unit(&type_path.path) {
+        return Some(unit);
+    }
 
-            // Handle common types
-            if path.is_ident("String") {
-                return Ok(quote! { #crate_name::CapnpType::Text });
-            }
+    let segment = type_path.path.segments.last()?;
+    if segment.ident != "Option" && segment.ident != "Vec" && segment.ident != "Box" {
+        return None;
+    }
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => match args.args.first() {
+            Some(syn::GenericArgument::Type(inner)) => time_type_unit(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
 
-            if path.is_ident("bool") {
-                return Ok(quote! { #crate_name::CapnpType::Bool });
-            }
+/// Returns the unit a time field is stored in, from its `time_unit` attribute or the
+/// type's default, checking that the attribute is known and only used on time fields
+fn field_time_unit(field: &syn::Field, attrs: &CapnpAttrs) -> Result<Option<String>> {
+    let default_unit = time_type_unit(&field.ty);
+    let Some(time_unit) = &attrs.time_unit else {
+        // An explicit `type` replaces the `UInt64` the unit would describe
+        if attrs.capnp_type.is_some() {
+            return Ok(None);
+        }
+        return Ok(default_unit.map(str::to_string));
+    };
 
-            // Handle integer types
-            if path.is_ident("u8") {
-                return Ok(quote! { #crate_name::CapnpType::UInt8 });
-            }
-            if path.is_ident("u16") {
-                return Ok(quote! { #crate_name::CapnpType::UInt16 });
-            }
-            if path.is_ident("u32") {
-                return Ok(quote! { #crate_name::CapnpType::UInt32 });
-            }
-            if path.is_ident("u64") {
-                return Ok(quote! { #crate_name::CapnpType::UInt64 });
-            }
-            if path.is_ident("i8") {
-                return Ok(quote! { #crate_name::CapnpType::Int8 });
-            }
-            if path.is_ident("i16") {
-                return Ok(quote! { #crate_name::CapnpType::Int16 });
-            }
-            if path.is_ident("i32") {
-                return Ok(quote! { #crate_name::CapnpType::Int32 });
-            }
-            if path.is_ident("i64") {
-                return Ok(quote! { #crate_name::CapnpType::Int64 });
-            }
-            if path.is_ident("f32") {
-                return Ok(quote! { #crate_name::CapnpType::Float32 });
-            }
-            if path.is_ident("f64") {
-                return Ok(quote! { #crate_name::CapnpType::Float64 });
-            }
-
-            // Handle Vec<T>
-            if let Some(segment) = path.segments.first() {
-                if segment.ident == "Vec" {
-                    if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
-                        if let Some(syn::GenericArgument::Type(inner_type)) = args.args.first() {
-                            let inner_type_tokens =
-                                generate_capnp_type_tokens(inner_type, crate_name)?;
-                            return Ok(quote! {
-                                #crate_name::CapnpType::List(
-                                    Box::new(#inner_type_tokens)
-                                )
-                            });
-                        }
-                    }
-                }
-            }
+    if default_unit.is_none() {
+        return Err(Error::new_spanned(
+            field,
+            "time_unit can only be used on Duration and SystemTime fields",
+        ));
+    }
+    if !TIME_UNITS.contains(&time_unit.as_str()) {
+        return Err(Error::new_spanned(
+            field,
+            format!(
+                "Unknown time_unit '{}', expected one of: {}",
+                time_unit,
+                TIME_UNITS.join(", ")
+            ),
+        ));
+    }
 
-            // Handle user-defined types
-            let type_name = path
-                .segments
-                .iter()
-                .map(|s| s.ident.to_string())
-                .collect::<Vec<_>>()
-                .join("::");
+    Ok(Some(time_unit.clone()))
+}
 
-            Ok(quote! {
-                #crate_name::CapnpType::UserDefined(#type_name.to_string())
-            })
-        }
-        _ => Err(Error::new_spanned(ty, "Unsupported type")),
+/// The names of a type's generic parameters
+struct GenericParams {
+    /// Type parameters, which become Cap'n Proto generic parameters
+    types: Vec<String>,
+    /// Const parameters, which aren't part of the schema
+    consts: Vec<String>,
+}
+
+/// Returns the names of the type's generic type and const parameters (lifetimes are
+/// not part of the schema)
+fn extract_generic_params(input: &DeriveInput) -> GenericParams {
+    GenericParams {
+        types: input
+            .generics
+            .type_params()
+            .map(|param| param.ident.to_string())
+            .collect(),
+        consts: input
+            .generics
+            .const_params()
+            .map(|param| param.ident.to_string())
+            .collect(),
     }
 }
 
-fn extract_capnp_id(attrs: &[Attribute]) -> Result<u32> {
-    for attr in attrs {
-        if attr.path().is_ident("capnp") {
-            let mut id: Option<u32> = None;
-            let _ = attr.parse_nested_meta(|meta| {
-                if meta.path.is_ident("id") {
-                    let value = meta.value()?;
-                    let lit: Lit = value.parse()?;
-                    if let Lit::Int(lit_int) = lit {
-                        id = Some(lit_int.base10_parse()?);
-                    }
-                } else {
-                    // Skip other attributes
-                    if meta.input.peek(syn::Token![=]) {
-                        let _: Token![=] = meta.input.parse()?;
-                        let _: LitStr = meta.input.parse()?;
-                    }
-                }
-                Ok(())
-            });
-            if let Some(id) = id {
-                return Ok(id);
+/// All `#[capnp(...)]` settings found on a type, field or variant
+#[derive(Debug, Default)]
+struct CapnpAttrs {
+    /// `id = N`: the field or variant ordinal
+    id: Option<u32>,
+    /// `ids = [N, ...]`: ordinals for the members of a tuple group or tuple variant
+    ids: Vec<u32>,
+    /// `group`: the tuple-typed field becomes a named group
+    group: bool,
+    /// `group = "..."`: the field is a member of the named group
+    group_name: Option<String>,
+    /// `default = <literal>`: the field's default value
+    default: Option<capnp_model::DefaultValue>,
+    /// `name = "..."`: overrides the generated field name
+    name: Option<String>,
+    /// `extra = "..."`: verbatim fields kept for backwards compatibility
+    extra: Vec<String>,
+    /// `deprecated`: the field only reserves its ID
+    deprecated: bool,
+    /// `raw = "..."`: verbatim lines appended to the struct body, never validated
+    raw: Vec<String>,
+    /// `type_id = 0x...`: pins the type's Cap'n Proto ID
+    type_id: Option<u64>,
+    /// `file = "..."`: the schema file this type is recorded into
+    file: Option<String>,
+    /// `file_id = 0x...`: writes `file` directly, with this ID, from this type's derive
+    file_id: Option<u64>,
+    /// `allow_undefined_types`: the file written with `file_id` may reference types it
+    /// doesn't define
+    allow_undefined_types: bool,
+    /// `as = "data" | "text"`: overrides the mapping of a `String` or byte field
+    as_type: Option<String>,
+    /// `type = "..."`: replaces the inferred Cap'n Proto type of a field
+    capnp_type: Option<String>,
+    /// `time_unit = "..."`: the unit a `Duration`/`SystemTime` is stored in
+    time_unit: Option<String>,
+    /// `transparent`: the type stands in for its single field's type
+    transparent: bool,
+    /// `embed_schema`: also emit the rendered schema as a `CAPNP_SCHEMA` constant
+    embed_schema: bool,
+    /// `strict`: check at compile time that user-defined field types derive `CapnpType`
+    strict: bool,
+    /// `mapping`: the field's type comes from its `CapnpTypeMapping` implementation
+    mapping: bool,
+    /// `union_name = "..."`: renders an enum's union as a named union
+    union_name: Option<String>,
+    /// `allow_empty`: the type is intentionally empty
+    allow_empty: bool,
+    /// `tagged`: adds a `tag @0 :UInt16` field before an enum's union
+    tagged: bool,
+    /// `rename_all = "..."`: how the type's field names are transformed by default
+    rename_all: Option<String>,
+}
+
+/// Parses a `default = ...` value: an integer, float, bool or string literal, optionally
+/// negated
+fn parse_default_value(expr: &syn::Expr) -> Result<capnp_model::DefaultValue> {
+    use capnp_model::DefaultValue;
+
+    let (negated, lit) = match expr {
+        syn::Expr::Lit(syn::ExprLit { lit, .. }) => (false, lit),
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr,
+            ..
+        }) => match expr.as_ref() {
+            syn::Expr::Lit(syn::ExprLit { lit, .. }) => (true, lit),
+            _ => return Err(Error::new_spanned(expr, "Expected a literal default value")),
+        },
+        _ => return Err(Error::new_spanned(expr, "Expected a literal default value")),
+    };
+
+    match lit {
+        // Values above `i64::MAX` are kept as unsigned, for `u64` fields
+        syn::Lit::Int(lit) => {
+            let value: u64 = lit.base10_parse()?;
+            let signed = if negated {
+                -i128::from(value)
+            } else {
+                i128::from(value)
+            };
+            match i64::try_from(signed) {
+                Ok(value) => Ok(DefaultValue::Int(value)),
+                Err(_) if !negated => Ok(DefaultValue::UInt(value)),
+                Err(_) => Err(Error::new_spanned(lit, "Integer default is out of range")),
             }
         }
+        syn::Lit::Float(lit) => {
+            let value: f64 = lit.base10_parse()?;
+            Ok(DefaultValue::Float(if negated { -value } else { value }))
+        }
+        syn::Lit::Bool(lit) if !negated => Ok(DefaultValue::Bool(lit.value)),
+        syn::Lit::Str(lit) if !negated => Ok(DefaultValue::Text(lit.value())),
+        _ => Err(Error::new_spanned(
+            expr,
+            "Default values must be integer, float, bool or string literals",
+        )),
     }
-    Err(Error::new(
-        Span::call_site(),
-        "Missing required capnp:id attribute",
-    ))
 }
 
-fn extract_custom_name(attrs: &[Attribute]) -> Result<Option<String>> {
+/// Parses every `#[capnp(...)]` attribute in one pass
+///
+/// Known keys are validated; unknown keys and their values are skipped so that
+/// attributes meant for newer versions don't break parsing of the rest.
+fn parse_capnp_attrs(attrs: &[Attribute]) -> Result<CapnpAttrs> {
+    let mut result = CapnpAttrs::default();
+
     for attr in attrs {
-        if attr.path().is_ident("capnp") {
-            let mut name: Option<String> = None;
-            let _ = attr.parse_nested_meta(|meta| {
-                if meta.path.is_ident("name") {
-                    let value = meta.value()?;
-                    let lit: Lit = value.parse()?;
-                    if let Lit::Str(lit_str) = lit {
-                        name = Some(lit_str.value());
-                    }
-                } else {
-                    // Skip other attributes
-                    if meta.input.peek(syn::Token![=]) {
-                        let _: Token![=] = meta.input.parse()?;
-                        if meta.path.is_ident("id") {
-                            let _: LitInt = meta.input.parse()?;
-                        } else {
-                            let _: LitStr = meta.input.parse()?;
-                        }
+        if !attr.path().is_ident("capnp") {
+            continue;
+        }
+
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                // syn stores integer literals as decimal digits, so `base10_parse` also
+                // accepts `0x`, `0o` and `0b` literals
+                let lit: LitInt = meta.value()?.parse()?;
+                set_once(&mut result.id, lit.base10_parse()?, &meta, "id")?;
+            } else if meta.path.is_ident("type_id") {
+                let lit: LitInt = meta.value()?.parse()?;
+                set_once(&mut result.type_id, lit.base10_parse()?, &meta, "type_id")?;
+            } else if meta.path.is_ident("file_id") {
+                let lit: LitInt = meta.value()?.parse()?;
+                set_once(&mut result.file_id, lit.base10_parse()?, &meta, "file_id")?;
+            } else if meta.path.is_ident("ids") {
+                let array: syn::ExprArray = meta.value()?.parse()?;
+                for elem in &array.elems {
+                    match elem {
+                        syn::Expr::Lit(syn::ExprLit {
+                            lit: syn::Lit::Int(lit),
+                            ..
+                        }) => result.ids.push(lit.base10_parse()?),
+                        _ => return Err(Error::new_spanned(elem, "Expected an integer ID")),
                     }
                 }
-                Ok(())
-            });
-            if name.is_some() {
-                return Ok(name);
+            } else if meta.path.is_ident("default") {
+                let expr: syn::Expr = meta.value()?.parse()?;
+                result.default.get_or_insert(parse_default_value(&expr)?);
+            } else if meta.path.is_ident("group") && meta.input.peek(Token![=]) {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.group_name.get_or_insert(lit.value());
+            } else if meta.path.is_ident("group") {
+                result.group = true;
+            } else if meta.path.is_ident("name") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.name.get_or_insert(lit.value());
+            } else if meta.path.is_ident("extra") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.extra.push(lit.value());
+            } else if meta.path.is_ident("raw") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.raw.push(lit.value());
+            } else if meta.path.is_ident("file") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.file.get_or_insert(lit.value());
+            } else if meta.path.is_ident("as") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.as_type.get_or_insert(lit.value());
+            } else if meta.path.is_ident("type") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.capnp_type.get_or_insert(lit.value());
+            } else if meta.path.is_ident("time_unit") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.time_unit.get_or_insert(lit.value());
+            } else if meta.path.is_ident("embed_schema") {
+                result.embed_schema = true;
+            } else if meta.path.is_ident("deprecated") {
+                result.deprecated = true;
+            } else if meta.path.is_ident("allow_undefined_types") {
+                result.allow_undefined_types = true;
+            } else if meta.path.is_ident("allow_empty") {
+                result.allow_empty = true;
+            } else if meta.path.is_ident("tagged") {
+                result.tagged = true;
+            } else if meta.path.is_ident("rename_all") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.rename_all.get_or_insert(lit.value());
+            } else if meta.path.is_ident("union_name") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.union_name.get_or_insert(lit.value());
+            } else if meta.path.is_ident("mapping") {
+                result.mapping = true;
+            } else if meta.path.is_ident("strict") {
+                result.strict = true;
+            } else if meta.path.is_ident("transparent") {
+                result.transparent = true;
+            } else if meta.input.peek(Token![=]) {
+                // Skip unknown `key = value` attributes, whatever the value is
+                let _: syn::Expr = meta.value()?.parse()?;
+            } else if !meta.input.is_empty() && !meta.input.peek(Token![,]) {
+                // Skip unknown `key(...)` attributes
+                let _: proc_macro2::TokenTree = meta.input.parse()?;
             }
-        }
+            Ok(())
+        })?;
     }
-    Ok(None)
-}
 
-fn extract_extra_fields(attrs: &[Attribute]) -> Result<Vec<String>> {
-    let mut extra_fields = Vec::new();
+    Ok(result)
+}
 
-    for attr in attrs {
-        if attr.path().is_ident("capnp") {
-            let _ = attr.parse_nested_meta(|meta| {
-                if meta.path.is_ident("extra") {
-                    let value = meta.value()?;
-                    let lit: Lit = value.parse()?;
-                    if let Lit::Str(lit_str) = lit {
-                        extra_fields.push(lit_str.value());
-                    }
-                } else {
-                    // Skip other attributes
-                    if meta.input.peek(syn::Token![=]) {
-                        let _: Token![=] = meta.input.parse()?;
-                        if meta.path.is_ident("id") {
-                            let _: LitInt = meta.input.parse()?;
-                        } else {
-                            let _: LitStr = meta.input.parse()?;
-                        }
-                    }
-                }
-                Ok(())
-            });
-        }
+/// Stores an ID attribute's value, rejecting a second one for the same item, which
+/// would otherwise be silently dropped
+fn set_once<T>(
+    slot: &mut Option<T>,
+    value: T,
+    meta: &syn::meta::ParseNestedMeta,
+    name: &str,
+) -> Result<()> {
+    if slot.replace(value).is_some() {
+        return Err(meta.error(format!("Duplicate capnp({}) attribute", name)));
     }
+    Ok(())
+}
 
-    Ok(extra_fields)
+/// Returns the required `id`, erroring at `spanned` if it's missing
+fn required_id(attrs: &CapnpAttrs, spanned: impl quote::ToTokens) -> Result<u32> {
+    attrs
+        .id
+        .ok_or_else(|| Error::new_spanned(spanned, "Missing required capnp:id attribute"))
 }
 
-fn extract_schema_filename(attrs: &[Attribute]) -> Result<String> {
-    for attr in attrs {
-        if attr.path().is_ident("capnp") {
-            let mut filename: Option<String> = None;
-            let _ = attr.parse_nested_meta(|meta| {
-                if meta.path.is_ident("file") {
-                    let value = meta.value()?;
-                    let lit: Lit = value.parse()?;
-                    if let Lit::Str(lit_str) = lit {
-                        filename = Some(lit_str.value());
-                    }
-                } else {
-                    // Skip other attributes
-                    if meta.input.peek(syn::Token![=]) {
-                        let _: Token![=] = meta.input.parse()?;
-                        if meta.path.is_ident("id") {
-                            let _: LitInt = meta.input.parse()?;
-                        } else {
-                            let _: LitStr = meta.input.parse()?;
-                        }
-                    }
-                }
-                Ok(())
-            });
-            if let Some(filename) = filename {
-                return Ok(filename);
-            }
-        }
+/// Reads an explicit integer discriminant like `Active = 3`, used as the ordinal of a
+/// unit variant without `capnp(id)`
+///
+/// Returns `None` for variants without a discriminant or with a non-literal one.
+fn discriminant_id(variant: &syn::Variant) -> Result<Option<u32>> {
+    match &variant.discriminant {
+        Some((
+            _,
+            syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Int(int),
+                ..
+            }),
+        )) => int.base10_parse().map(Some),
+        _ => Ok(None),
     }
-    Err(Error::new(
-        Span::call_site(),
-        "No capnp file attribute found",
-    ))
 }
 
 static TARGET_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
@@ -861,3 +2061,483 @@ static TARGET_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
     writeln!(logfile, "metadata dir: {metadata_dir:?}").unwrap();
     metadata_dir
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorded_items(filename: &str) -> Vec<capnp_model::SchemaItem> {
+        let files = SCHEMA_FILES.lock().unwrap();
+        files[&schema_file_key(filename)].1.clone()
+    }
+
+    #[test]
+    fn test_reexpansion_keeps_every_type_once() {
+        let filename = "reexpansion_test.capnp";
+        let a = capnp_model::SchemaItem::Struct(capnp_model::Struct::new("A".to_string()));
+        let b = capnp_model::SchemaItem::Struct(capnp_model::Struct::new("B".to_string()));
+        let mut changed_a = capnp_model::Struct::new("A".to_string());
+        changed_a.add_field(capnp_model::Field::new(
+            "x".to_string(),
+            0,
+            capnp_model::CapnpType::UInt32,
+        ));
+        let changed_a = capnp_model::SchemaItem::Struct(changed_a);
+
+        init_schema_file(filename, 0xfbb45a811fbe71f5);
+        record_schema_item(&a, filename).unwrap();
+        record_schema_item(&b, filename).unwrap();
+
+        // A rebuild re-runs the file macro and only some of the derives
+        init_schema_file(filename, 0xfbb45a811fbe71f5);
+        record_schema_item(&changed_a, filename).unwrap();
+
+        assert_eq!(recorded_items(filename), vec![changed_a, b]);
+    }
+
+    #[test]
+    fn test_clashing_variant_ordinals_are_rejected() {
+        let input: DeriveInput = syn::parse_quote! {
+            enum Job {
+                #[capnp(id = 0)]
+                Idle,
+                Busy(#[capnp(id = 0)] String),
+            }
+        };
+
+        let err = generate_capnp_type(&input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "In 'Job': Duplicate ID 0 found in: union variant 'idle', union group 'busy' field \
+             'field0'"
+        );
+    }
+
+    #[test]
+    fn test_gap_in_variant_ordinals_is_rejected() {
+        let input: DeriveInput = syn::parse_quote! {
+            enum Light {
+                #[capnp(id = 0)]
+                Off,
+                #[capnp(id = 2)]
+                On,
+            }
+        };
+
+        let err = generate_capnp_type(&input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "In 'Light': Union skips ordinal 1; union ordinals can't have gaps"
+        );
+    }
+
+    #[test]
+    fn test_tagged_enum_reserves_tag_id() {
+        let input: DeriveInput = syn::parse_quote! {
+            #[capnp(tagged)]
+            enum Light {
+                #[capnp(id = 0)]
+                Off,
+                #[capnp(id = 1)]
+                On,
+            }
+        };
+
+        let err = generate_capnp_type(&input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "capnp(tagged) reserves @0 for the tag field; number the variants from @1"
+        );
+    }
+
+    #[test]
+    fn test_capnp_id_takes_precedence_over_discriminant() {
+        // The discriminant doesn't fit a u32, but is never read
+        let input: DeriveInput = syn::parse_quote! {
+            enum Status {
+                #[capnp(id = 0)]
+                Active = -1,
+                #[capnp(id = 1)]
+                Banned = 5000000000,
+            }
+        };
+        let tokens = generate_capnp_type(&input).unwrap().to_string();
+        assert!(tokens.contains(&quote!(name: "active".to_string()).to_string()));
+    }
+
+    #[test]
+    fn test_duplicate_ids_are_rejected() {
+        let repeated: DeriveInput = syn::parse_quote! {
+            struct Request {
+                #[capnp(id = 0, id = 1)]
+                url: String,
+            }
+        };
+        assert_eq!(
+            generate_capnp_type(&repeated).unwrap_err().to_string(),
+            "Duplicate capnp(id) attribute"
+        );
+
+        let split: DeriveInput = syn::parse_quote! {
+            #[capnp(type_id = 0xd7a1c25a6fdc3e2b)]
+            #[capnp(type_id = 0xd7a1c25a6fdc3e2c)]
+            struct Request {
+                #[capnp(id = 0)]
+                url: String,
+            }
+        };
+        assert_eq!(
+            generate_capnp_type(&split).unwrap_err().to_string(),
+            "Duplicate capnp(type_id) attribute"
+        );
+    }
+
+    #[test]
+    fn test_snake_case_rename_all_is_rejected() {
+        let input: DeriveInput = syn::parse_quote! {
+            #[capnp(rename_all = "snake_case")]
+            struct Request {
+                #[capnp(id = 0)]
+                user_id: u64,
+            }
+        };
+
+        let err = generate_capnp_type(&input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "rename_all = \"snake_case\" produces names with underscores, which capnpc rejects"
+        );
+    }
+
+    #[test]
+    fn test_underscored_verbatim_name_is_rejected() {
+        let input: DeriveInput = syn::parse_quote! {
+            #[capnp(rename_all = "none")]
+            struct Request {
+                #[capnp(id = 0)]
+                user_id: u64,
+            }
+        };
+
+        let err = generate_capnp_type(&input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Field name 'user_id' is not a valid Cap'n Proto name; set one with #[capnp(name = \"...\")]"
+        );
+    }
+
+    #[test]
+    fn test_unknown_rename_all_is_rejected() {
+        let input: DeriveInput = syn::parse_quote! {
+            #[capnp(rename_all = "PascalCase")]
+            struct Request {
+                #[capnp(id = 0)]
+                http_url: String,
+            }
+        };
+
+        let err = generate_capnp_type(&input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown rename_all 'PascalCase', expected one of: none, camelCase"
+        );
+    }
+
+    #[test]
+    fn test_schema_file_args() {
+        let (filename, file_id) =
+            parse_schema_file_args(quote!("demo.capnp", 0xfbb45a811fbe71f5)).unwrap();
+        assert_eq!(filename, "demo.capnp");
+        assert_eq!(file_id, 0xfbb45a811fbe71f5);
+
+        let (_, file_id) =
+            parse_schema_file_args(quote!("demo.capnp", 18137221109957685749)).unwrap();
+        assert_eq!(file_id, 0xfbb45a811fbe71f5);
+
+        let err = parse_schema_file_args(quote!("demo.capnp", 0x42)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "File ID 0x42 must have its high bit set (e.g. 0x8000000000000042)"
+        );
+    }
+
+    #[test]
+    fn test_nested_vecs() {
+        let generics = GenericParams {
+            types: Vec::new(),
+            consts: Vec::new(),
+        };
+        let render = |ty: syn::Type| {
+            rust_type_to_capnp_model_type(&ty, &generics)
+                .unwrap()
+                .render()
+        };
+
+        assert_eq!(
+            render(syn::parse_quote!(Vec<Vec<u32>>)),
+            "List(List(UInt32))"
+        );
+        assert_eq!(
+            render(syn::parse_quote!(Vec<Vec<Vec<String>>>)),
+            "List(List(List(Text)))"
+        );
+        assert_eq!(
+            render(syn::parse_quote!(Vec<Vec<Vec<u8>>>)),
+            "List(List(Data))"
+        );
+    }
+
+    #[test]
+    fn test_only_std_time_types_are_time_types() {
+        let generics = GenericParams {
+            types: Vec::new(),
+            consts: Vec::new(),
+        };
+        for ty in [
+            syn::parse_quote!(Duration),
+            syn::parse_quote!(std::time::Duration),
+            syn::parse_quote!(core::time::Duration),
+        ] {
+            assert_eq!(
+                rust_type_to_capnp_model_type(&ty, &generics).unwrap(),
+                capnp_model::CapnpType::UInt64
+            );
+            assert_eq!(time_type_unit(&ty), Some("nanos"));
+        }
+
+        let chrono: syn::Type = syn::parse_quote!(chrono::Duration);
+        assert_eq!(
+            rust_type_to_capnp_model_type(&chrono, &generics).unwrap(),
+            capnp_model::CapnpType::UserDefined("chrono::Duration".to_string())
+        );
+        assert_eq!(time_type_unit(&chrono), None);
+        assert_eq!(
+            time_type_unit(&syn::parse_quote!(Option<Vec<SystemTime>>)),
+            Some("millis")
+        );
+    }
+
+    #[test]
+    fn test_unsupported_generic_uses_are_rejected() {
+        let supported: DeriveInput = syn::parse_quote! {
+            struct Wrapper<T> {
+                #[capnp(id = 0)]
+                value: T,
+                #[capnp(id = 1)]
+                history: Vec<T>,
+            }
+        };
+        let tokens = generate_capnp_type(&supported).unwrap().to_string();
+        assert!(tokens.contains("impl < T > Wrapper < T >"));
+
+        let optional: DeriveInput = syn::parse_quote! {
+            struct Wrapper<T> {
+                #[capnp(id = 0)]
+                value: Option<T>,
+            }
+        };
+        assert_eq!(
+            generate_capnp_type(&optional).unwrap_err().to_string(),
+            "Option of a generic parameter is not supported"
+        );
+
+        let fallible: DeriveInput = syn::parse_quote! {
+            struct Wrapper<T> {
+                #[capnp(id = 0)]
+                value: Result<u32, T>,
+            }
+        };
+        assert_eq!(
+            generate_capnp_type(&fallible).unwrap_err().to_string(),
+            "Result of a generic parameter is not supported"
+        );
+    }
+
+    #[test]
+    fn test_const_generic_array_length_is_rejected() {
+        let generics = GenericParams {
+            types: Vec::new(),
+            consts: vec!["N".to_string()],
+        };
+
+        let literal: syn::Type = syn::parse_quote!([u8; 32]);
+        assert_eq!(
+            rust_type_to_capnp_model_type(&literal, &generics).unwrap(),
+            capnp_model::CapnpType::Data
+        );
+
+        let generic: syn::Type = syn::parse_quote!([u8; N]);
+        let err = rust_type_to_capnp_model_type(&generic, &generics).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Array length `N` is a const generic parameter")
+        );
+    }
+
+    #[test]
+    fn test_compile_options() {
+        let (module_decl, options) = parse_compile_options(quote!(
+            pub mod demo_capnp,
+            import_paths = ["../shared", "vendor"],
+            output_path = "generated",
+            allow_undefined_types = true,
+        ))
+        .unwrap();
+        assert_eq!(
+            module_decl.to_string(),
+            quote!(pub mod demo_capnp).to_string()
+        );
+        assert_eq!(
+            options,
+            CompileOptions {
+                import_paths: vec!["../shared".to_string(), "vendor".to_string()],
+                output_path: Some("generated".to_string()),
+                allow_undefined_types: true,
+            }
+        );
+
+        let (_, options) = parse_compile_options(quote!(pub mod demo_capnp)).unwrap();
+        assert_eq!(options, CompileOptions::default());
+
+        let err =
+            parse_compile_options(quote!(mod demo_capnp, import_paths = "../shared")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "import_paths must be an array of string literals"
+        );
+    }
+
+    #[test]
+    fn test_schema_file_output_ignores_expansion_order() {
+        let person =
+            capnp_model::SchemaItem::Struct(capnp_model::Struct::new("Person".to_string()));
+        let status =
+            capnp_model::SchemaItem::Struct(capnp_model::Struct::new("Status".to_string()));
+        let address =
+            capnp_model::SchemaItem::Struct(capnp_model::Struct::new("Address".to_string()));
+
+        let first = render_schema_file(
+            0xfbb45a811fbe71f5,
+            vec![person.clone(), status.clone(), address.clone()],
+            false,
+        )
+        .unwrap();
+        let second =
+            render_schema_file(0xfbb45a811fbe71f5, vec![status, address, person], false).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(
+            first,
+            "@0xfbb45a811fbe71f5;\n\nstruct Address {\n}\n\nstruct Person {\n}\n\nstruct Status {\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_schema_file_rejects_unrecorded_types() {
+        let mut person = capnp_model::Struct::new("Person".to_string());
+        person.add_field(capnp_model::Field::new(
+            "home".to_string(),
+            0,
+            capnp_model::CapnpType::UserDefined("Address".to_string()),
+        ));
+        let err = render_schema_file(
+            0xfbb45a811fbe71f5,
+            vec![capnp_model::SchemaItem::Struct(person)],
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            capnp_model::ValidationError::UndefinedType {
+                name: "Address".to_string(),
+                referenced_by: "Person".to_string(),
+            }
+        );
+
+        // A root type written straight from its derive reports it as a compile error
+        let input: DeriveInput = syn::parse_quote! {
+            #[capnp(file = "target/unrecorded_reference.capnp", file_id = 0xfbb45a811fbe71f5)]
+            struct Order {
+                #[capnp(id = 0)]
+                customer: Customer,
+            }
+        };
+        let err = generate_capnp_type(&input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to render schema: Type 'Customer' referenced by 'Order' is not defined"
+        );
+    }
+
+    #[test]
+    fn test_schema_file_allows_imported_types() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct Order {
+                #[capnp(id = 0, type = "Shared.Customer")]
+                customer: Customer,
+            }
+        };
+        let item = generate_schema_item_with_model(
+            &input,
+            &CapnpAttrs::default(),
+            &mut MappedTypes::new(),
+        )
+        .unwrap();
+
+        assert!(render_schema_file(0xfbb45a811fbe71f5, vec![item.clone()], false).is_err());
+        assert_eq!(
+            render_schema_file(0xfbb45a811fbe71f5, vec![item], true).unwrap(),
+            "@0xfbb45a811fbe71f5;\n\nstruct Order {\n  customer @0 :Shared.Customer;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_mapped_fields_stay_out_of_the_model() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct Invoice {
+                #[capnp(id = 0, mapping)]
+                total: Decimal,
+                #[capnp(id = 1)]
+                note: String,
+            }
+        };
+        let mut mapped = MappedTypes::new();
+        let item =
+            generate_schema_item_with_model(&input, &CapnpAttrs::default(), &mut mapped).unwrap();
+        let capnp_model::SchemaItem::Struct(invoice) = &item else {
+            panic!("expected a struct");
+        };
+        assert_eq!(invoice.fields[0].field_type, capnp_model::CapnpType::Void);
+        assert_eq!(mapped.len(), 1);
+        assert_eq!(
+            mapped[&0],
+            MappedType::Leaf(Box::new(syn::parse_quote!(Decimal)))
+        );
+
+        let tokens = generate_capnp_type(&input).unwrap().to_string();
+        assert!(tokens.contains(
+            &quote!(<Decimal as code_first_capnp::CapnpTypeMapping>::capnp_type()).to_string()
+        ));
+
+        let with_default: DeriveInput = syn::parse_quote! {
+            struct Invoice {
+                #[capnp(id = 0, mapping, default = 0)]
+                total: Decimal,
+            }
+        };
+        assert_eq!(
+            generate_capnp_type(&with_default).unwrap_err().to_string(),
+            "capnp(default) cannot be combined with capnp(mapping)"
+        );
+    }
+
+    #[test]
+    fn test_schema_file_ends_with_one_newline() {
+        let empty = render_schema_file(0xfbb45a811fbe71f5, Vec::new(), false).unwrap();
+        assert_eq!(empty, "@0xfbb45a811fbe71f5;\n");
+
+        let item = capnp_model::SchemaItem::Struct(capnp_model::Struct::new("A".to_string()));
+        let single = render_schema_file(0xfbb45a811fbe71f5, vec![item], false).unwrap();
+        assert_eq!(single, "@0xfbb45a811fbe71f5;\n\nstruct A {\n}\n");
+    }
+}
@@ -78,11 +78,1268 @@
 //!
 //! Enums are rendered as Cap'n Proto structs containing unions. Variants with associated
 //! data become **groups** within the union rather than separate struct definitions.
+//!
+//! ## Strict Mode
+//!
+//! Field types the macro doesn't recognise are assumed to be other derived types. With
+//! `#[capnp(strict)]`, a field type that doesn't derive `CapnpType` is a compile error
+//! instead of a dangling reference in the schema:
+//!
+//! ```compile_fail,E0599
+//! use code_first_capnp::CapnpType;
+//!
+//! struct Adress {
+//!     city: String,
+//! }
+//!
+//! #[derive(CapnpType)]
+//! #[capnp(strict)]
+//! struct Person {
+//!     #[capnp(id = 0)]
+//!     address: Adress,
+//! }
+//! ```
 
 pub use capnp_model::{
-    CapnpType, Field as CapnpField, Schema, SchemaItem, Struct, Union, UnionVariant,
-    UnionVariantInner,
+    CapnpType, DefaultValue, Enum, Enumerant, Field as CapnpField, FieldOrder, Group, IdLocation,
+    Interface, LanguageAnnotation, Method, Param, RenderOptions, Schema, SchemaItem, Struct,
+    Transparent, TypeKind, Union, UnionVariant, UnionVariantInner, ValidationError,
+    ValidationWarning, check_file_id, format_file_id, parse_file_id, stable_file_id,
+    validate_items, with_high_bit,
 };
 
+/// The types and functions needed to build and render schemas, for glob importing
+///
+/// ```rust
+/// use code_first_capnp::prelude::*;
+///
+/// let mut person = Struct::new("Person".to_string());
+/// person.add_field(CapnpField::new("name".to_string(), 0, CapnpType::Text));
+/// let mut contact = Union::new();
+/// contact.add_variant(UnionVariant::new("none".to_string(), 1, CapnpType::Void));
+/// contact.add_variant(UnionVariant::new("email".to_string(), 2, CapnpType::Text));
+/// person.set_union(contact);
+///
+/// let schema = Schema::with_struct(person);
+/// assert_eq!(
+///     schema.render().unwrap(),
+///     "struct Person {\n  name @0 :Text;\n  union {\n    none @1 :Void;\n    email @2 :Text;\n  }\n}\n"
+/// );
+/// ```
+pub mod prelude {
+    pub use crate::{
+        CapnpField, CapnpType, CapnpTypeMapping, DefaultValue, FieldOrder, Group, RenderOptions,
+        Schema, SchemaItem, Struct, Union, UnionVariant, UnionVariantInner, ValidationError,
+        body_for_item, file_for_item, schema_for_item, schema_from_items,
+    };
+}
+
+// Lets derived code name this crate the same way inside and outside of it
+extern crate self as code_first_capnp;
+
 // Re-export the proc macros
 pub use code_first_capnp_macros::{CapnpType, capnp_schema_file, complete_capnp_schema};
+
+/// Maps a Rust type that the derive doesn't know about to a Cap'n Proto type
+///
+/// Implement this for types from other crates (e.g. a decimal or byte buffer type), then
+/// mark fields of that type with `#[capnp(mapping)]`. The derive looks through `Vec`,
+/// `Option`, `Box` and arrays, so a `Vec<Decimal>` field only needs the implementation
+/// for `Decimal`:
+///
+/// ```rust,ignore
+/// impl CapnpTypeMapping for Decimal {
+///     fn capnp_type() -> code_first_capnp::CapnpType {
+///         code_first_capnp::CapnpType::Text
+///     }
+/// }
+///
+/// #[derive(CapnpType)]
+/// struct Price {
+///     #[capnp(id = 0, mapping)]
+///     amount: Decimal,
+/// }
+/// ```
+///
+/// Mapped types are resolved when `get_capnp_schema()` runs, so types with mapped fields
+/// can't use `#[capnp(file = ...)]` or `#[capnp(embed_schema)]`.
+pub trait CapnpTypeMapping {
+    /// The Cap'n Proto type that values of this type are stored as
+    fn capnp_type() -> CapnpType;
+}
+
+/// A derived type recorded in the registry by `#[derive(CapnpType)]`
+#[cfg(feature = "registry")]
+#[doc(hidden)]
+pub struct RegisteredSchema {
+    pub get_capnp_schema: fn() -> SchemaItem,
+}
+
+#[cfg(feature = "registry")]
+inventory::collect!(RegisteredSchema);
+
+#[cfg(feature = "registry")]
+#[doc(hidden)]
+pub use inventory as __inventory;
+
+#[cfg(feature = "registry")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __register_capnp_type {
+    ($name:ident) => {
+        $crate::__inventory::submit! {
+            $crate::RegisteredSchema {
+                get_capnp_schema: $name::get_capnp_schema,
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "registry"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __register_capnp_type {
+    ($name:ident) => {};
+}
+
+/// Returns the schema of every non-generic type in the binary that derives
+/// `CapnpType`, sorted by name
+///
+/// Requires the `registry` feature.
+#[cfg(feature = "registry")]
+pub fn all_registered_schemas() -> Vec<SchemaItem> {
+    let mut items: Vec<SchemaItem> = inventory::iter::<RegisteredSchema>
+        .into_iter()
+        .map(|registered| (registered.get_capnp_schema)())
+        .collect();
+    items.sort_by(|a, b| a.name().cmp(b.name()));
+    items
+}
+
+/// Renders a set of schema items (e.g. from `get_capnp_schema()`) as schema text
+///
+/// The items are borrowed and rendered in place, without being copied into a [`Schema`].
+/// The output has no `@0x...;` file ID line, so it can be embedded into a larger file.
+pub fn schema_from_items(items: &[SchemaItem]) -> Result<String, ValidationError> {
+    capnp_model::render_items(items)
+}
+
+/// Renders a set of schema items, taking ownership of them
+pub fn schema_from_owned(items: Vec<SchemaItem>) -> Result<String, ValidationError> {
+    let mut schema = Schema::new();
+    schema.extend(items);
+    schema.render()
+}
+
+/// Renders a single schema item as schema text
+pub fn schema_for_item(item: &SchemaItem) -> Result<String, ValidationError> {
+    schema_from_items(std::slice::from_ref(item))
+}
+
+/// Renders a single schema item as a complete schema file, returning the file ID
+/// alongside the text so it can be recorded
+///
+/// The ID comes from [`stable_file_id`], so it only changes if the item is renamed.
+pub fn file_for_item(item: &SchemaItem) -> Result<(u64, String), ValidationError> {
+    let schema = single_item_schema(item);
+    let file_id = stable_file_id(&schema);
+    let text = format!("{}\n\n{}", format_file_id(file_id), schema.render()?);
+    Ok((file_id, text))
+}
+
+/// Renders a single schema item exactly as [`file_for_item`] does, but without the
+/// `@0x...;` file ID line, for embedding into a file that already has a header
+pub fn body_for_item(item: &SchemaItem) -> Result<String, ValidationError> {
+    single_item_schema(item).render()
+}
+
+fn single_item_schema(item: &SchemaItem) -> Schema {
+    let mut schema = Schema::new();
+    schema.add_item(item.clone());
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use std::num::{NonZero, NonZeroI64, NonZeroU16};
+    use std::time::Duration;
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Envelope<T> {
+        #[capnp(id = 0)]
+        payload: T,
+        #[capnp(id = 1)]
+        sender: String,
+        #[capnp(id = 2)]
+        history: Vec<T>,
+    }
+
+    #[test]
+    fn test_schema_from_owned_matches_borrowed() {
+        let items = vec![A::get_capnp_schema(), B::get_capnp_schema()];
+
+        let borrowed = schema_from_items(&items).unwrap();
+        let owned = schema_from_owned(items.clone()).unwrap();
+        assert_eq!(borrowed, owned);
+
+        let mut schema = Schema::new();
+        schema.extend(items);
+        assert_eq!(schema.render().unwrap(), borrowed);
+    }
+
+    #[test]
+    fn test_schema_from_items_has_no_file_id() {
+        let output =
+            schema_from_items(&[Member::get_capnp_schema(), Presence::get_capnp_schema()]).unwrap();
+
+        assert!(output.contains("struct Member {"));
+        assert!(output.contains("struct Presence {"));
+        assert!(!output.lines().any(|line| line.starts_with("@0x")));
+    }
+
+    #[test]
+    fn test_schema_for_item() {
+        let output = schema_for_item(&A::get_capnp_schema()).unwrap();
+        assert_eq!(output, "struct A {\n  b @0 :B;\n}\n");
+    }
+
+    #[test]
+    fn test_file_for_item() {
+        let (file_id, text) = file_for_item(&A::get_capnp_schema()).unwrap();
+
+        assert!(text.starts_with(&format!("@0x{:x};\n\n", file_id)));
+        assert!(text.ends_with("struct A {\n  b @0 :B;\n}\n"));
+        assert_eq!(parse_file_id(text.lines().next().unwrap()), Some(file_id));
+        assert_eq!(file_for_item(&A::get_capnp_schema()).unwrap().0, file_id);
+    }
+
+    #[test]
+    fn test_body_for_item() {
+        let body = body_for_item(&A::get_capnp_schema()).unwrap();
+        let (_, text) = file_for_item(&A::get_capnp_schema()).unwrap();
+
+        assert!(!body.contains("@0x"));
+        assert!(text.ends_with(&body));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Session {
+        #[capnp(id = 0)]
+        started_at: std::time::SystemTime,
+        #[capnp(id = 1, time_unit = "millis")]
+        timeout: std::time::Duration,
+        #[capnp(id = 2)]
+        elapsed: Duration,
+        #[capnp(id = 3, time_unit = "secs")]
+        ttl: Duration,
+        #[capnp(id = 4, time_unit = "micros")]
+        backoff: Option<Duration>,
+        #[capnp(id = 5)]
+        history: Vec<std::time::SystemTime>,
+    }
+
+    #[derive(CapnpType)]
+    #[capnp(extra = "oldUserId @1 :UInt64")]
+    #[capnp(extra = "deprecatedFlag @3 :Bool")]
+    #[allow(dead_code)]
+    struct UserProfile {
+        #[capnp(id = 4)]
+        active: bool,
+        #[capnp(id = 0)]
+        username: String,
+        #[capnp(id = 2)]
+        email: String,
+    }
+
+    #[test]
+    fn test_user_profile_snapshot() {
+        let mut schema = Schema::new();
+        schema.add_item(UserProfile::get_capnp_schema());
+        schema.set_field_order(FieldOrder::SourceOrder);
+
+        assert_eq!(
+            schema.render_canonical().unwrap(),
+            "struct UserProfile {\n  username @0 :Text;\n  email @2 :Text;\n  active @4 :Bool;\n  \
+             oldUserId @1 :UInt64;\n  deprecatedFlag @3 :Bool;\n}\n"
+        );
+    }
+
+    #[cfg(feature = "registry")]
+    #[test]
+    fn test_registry_lists_derived_types() {
+        let items = all_registered_schemas();
+        let names: Vec<&str> = items.iter().map(|item| item.name()).collect();
+
+        assert!(names.contains(&"Member"));
+        assert!(names.contains(&"Presence"));
+        assert!(!names.contains(&"Envelope"));
+        assert!(names.is_sorted());
+    }
+
+    /// Stands in for the `bytes` crate, since fields are recognised by type name
+    #[cfg(feature = "bytes")]
+    mod bytes {
+        pub struct Bytes;
+        pub struct BytesMut;
+    }
+
+    #[cfg(feature = "bytes")]
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Frame {
+        #[capnp(id = 0)]
+        payload: bytes::Bytes,
+        #[capnp(id = 1)]
+        scratch: Option<bytes::BytesMut>,
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_bytes_types_are_data() {
+        let output = Frame::get_capnp_schema().render().unwrap();
+
+        assert!(output.contains("payload @0 :Data;"));
+        assert!(output.contains("scratch @1 :OptionData;"));
+    }
+
+    #[test]
+    fn test_output_ends_with_one_newline() {
+        for output in [
+            schema_for_item(&Member::get_capnp_schema()).unwrap(),
+            schema_for_item(&Survey::get_capnp_schema()).unwrap(),
+            schema_from_items(&[Member::get_capnp_schema(), Presence::get_capnp_schema()]).unwrap(),
+        ] {
+            assert!(output.ends_with("}\n"), "{:?}", output);
+            assert!(!output.ends_with("\n\n"), "{:?}", output);
+        }
+    }
+
+    struct Decimal;
+
+    impl CapnpTypeMapping for Decimal {
+        fn capnp_type() -> CapnpType {
+            CapnpType::Text
+        }
+    }
+
+    struct Bytes;
+
+    impl CapnpTypeMapping for Bytes {
+        fn capnp_type() -> CapnpType {
+            CapnpType::Data
+        }
+    }
+
+    #[derive(CapnpType)]
+    #[capnp(strict)]
+    #[allow(dead_code)]
+    struct Invoice {
+        #[capnp(id = 0, mapping)]
+        total: Decimal,
+        #[capnp(id = 1, mapping)]
+        attachment: Bytes,
+        #[capnp(id = 2, mapping)]
+        lines: Vec<Decimal>,
+        #[capnp(id = 3, mapping)]
+        discount: Option<Box<Decimal>>,
+        #[capnp(id = 4, mapping)]
+        rates: [Option<Decimal>; 2],
+    }
+
+    #[test]
+    fn test_custom_type_mapping() {
+        let output = Invoice::get_capnp_schema().render().unwrap();
+
+        assert!(output.contains("total @0 :Text;"));
+        assert!(output.contains("attachment @1 :Data;"));
+        assert!(output.contains("lines @2 :List(Text);"));
+        assert!(output.contains("discount @3 :OptionText;"));
+        assert!(output.contains("rates @4 :List(OptionText);"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Counters {
+        #[capnp(id = 0)]
+        port: NonZeroU16,
+        #[capnp(id = 1)]
+        offset: NonZeroI64,
+        #[capnp(id = 2)]
+        total: std::num::NonZeroU32,
+        #[capnp(id = 3)]
+        seed: NonZero<u64>,
+    }
+
+    #[test]
+    fn test_nonzero_integers() {
+        let output = Counters::get_capnp_schema().render().unwrap();
+
+        assert!(output.contains("port @0 :UInt16;"));
+        assert!(output.contains("offset @1 :Int64;"));
+        assert!(output.contains("total @2 :UInt32;"));
+        assert!(output.contains("seed @3 :UInt64;"));
+    }
+
+    #[test]
+    fn test_time_types() {
+        let output = Session::get_capnp_schema().render().unwrap();
+
+        assert!(output.contains("startedAt @0 :UInt64;  # millis\n"));
+        assert!(output.contains("timeout @1 :UInt64;  # millis\n"));
+        assert!(output.contains("elapsed @2 :UInt64;  # nanos\n"));
+        assert!(output.contains("ttl @3 :UInt64;  # secs\n"));
+        assert!(output.contains("backoff @4 :OptionUInt64;  # micros\n"));
+        assert!(output.contains("history @5 :List(UInt64);  # millis\n"));
+    }
+
+    #[derive(CapnpType)]
+    #[capnp(transparent)]
+    #[allow(dead_code)]
+    struct UserId(u64);
+
+    #[derive(CapnpType)]
+    #[capnp(transparent)]
+    #[allow(dead_code)]
+    struct Tags {
+        values: Vec<String>,
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Account {
+        #[capnp(id = 0)]
+        owner: UserId,
+        #[capnp(id = 1)]
+        tags: Tags,
+    }
+
+    #[test]
+    fn test_transparent_newtypes() {
+        let output = schema_from_items(&[
+            UserId::get_capnp_schema(),
+            Tags::get_capnp_schema(),
+            Account::get_capnp_schema(),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            output,
+            "struct Account {\n  owner @0 :UInt64;\n  tags @1 :List(Text);\n}\n"
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Rgb(
+        #[capnp(id = 0)] u8,
+        #[capnp(id = 1)] u8,
+        #[capnp(id = 2)] u8,
+    );
+
+    #[test]
+    fn test_tuple_struct_fields_are_numbered() {
+        let output = Rgb::get_capnp_schema().render().unwrap();
+
+        assert_eq!(
+            output,
+            "struct Rgb {\n  field0 @0 :UInt8;\n  field1 @1 :UInt8;\n  field2 @2 :UInt8;\n}\n"
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[capnp(extra = "legacy @2 :Text", future_option = true)]
+    #[allow(dead_code)]
+    struct Labelled {
+        #[capnp(id = 0, name = "label", foo = bar, experimental(x = 1))]
+        tag: String,
+        #[capnp(unknown_flag, id = 1)]
+        count: u32,
+    }
+
+    #[test]
+    fn test_mixed_and_unknown_attributes() {
+        let output = Labelled::get_capnp_schema().render().unwrap();
+
+        assert_eq!(
+            output,
+            "struct Labelled {\n  label @0 :Text;\n  count @1 :UInt32;\n  legacy @2 :Text;\n}\n"
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    enum Presence {
+        #[capnp(id = 0)]
+        Online,
+        Away(#[capnp(id = 1)] String),
+    }
+
+    // No #[repr]: ordinals come from the capnp attributes only
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    enum Light {
+        #[capnp(id = 1)]
+        Red,
+        #[capnp(id = 0)]
+        Green,
+    }
+
+    #[test]
+    fn test_enum_without_repr() {
+        let output = Light::get_capnp_schema().render().unwrap();
+
+        assert_eq!(
+            output,
+            "struct Light {\n  union {\n    red @1 :Void;\n    green @0 :Void;\n  }\n}\n"
+        );
+    }
+
+    // Without capnp ids, explicit discriminants become the ordinals
+    #[derive(CapnpType)]
+    #[repr(u8)]
+    #[allow(dead_code)]
+    enum Level {
+        Low = 1,
+        High = 2,
+        #[capnp(id = 0)]
+        Off = 7,
+    }
+
+    #[test]
+    fn test_enum_ordinals_from_discriminants() {
+        let output = Level::get_capnp_schema().render().unwrap();
+
+        assert_eq!(
+            output,
+            "struct Level {\n  union {\n    low @1 :Void;\n    high @2 :Void;\n    off @0 :Void;\n  }\n}\n"
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    enum Media {
+        #[capnp(id = 0)]
+        Silence,
+        #[capnp(ids = [1, 2])]
+        Video(String, u32),
+    }
+
+    #[test]
+    fn test_positional_variant_ids() {
+        let output = Media::get_capnp_schema().render().unwrap();
+
+        assert!(
+            output.contains(
+                "video :group {\n      field0 @1 :Text;\n      field1 @2 :UInt32;\n    }"
+            )
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[capnp(union_name = "kind")]
+    #[allow(dead_code)]
+    enum Shape {
+        #[capnp(id = 0)]
+        Point,
+        Circle(#[capnp(id = 1)] f64),
+    }
+
+    #[test]
+    fn test_named_union() {
+        let output = Shape::get_capnp_schema().render().unwrap();
+
+        assert!(output.starts_with("struct Shape {\n  kind :union {\n    point @0 :Void;\n"));
+    }
+
+    #[derive(CapnpType)]
+    #[capnp(rename_all = "none")]
+    #[allow(dead_code, non_snake_case)]
+    struct Verbatim {
+        #[capnp(id = 0)]
+        userId: u64,
+        #[capnp(id = 1, name = "apiKey")]
+        api_key: String,
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code, non_snake_case)]
+    struct Acronyms {
+        #[capnp(id = 0)]
+        http_URL: String,
+        #[capnp(id = 1, name = "userID")]
+        user_id: u64,
+    }
+
+    #[test]
+    fn test_rename_all() {
+        assert_eq!(
+            Verbatim::get_capnp_schema().render().unwrap(),
+            "struct Verbatim {\n  userId @0 :UInt64;\n  apiKey @1 :Text;\n}\n"
+        );
+        assert_eq!(
+            Acronyms::get_capnp_schema().render().unwrap(),
+            "struct Acronyms {\n  httpUrl @0 :Text;\n  userID @1 :UInt64;\n}\n"
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[capnp(tagged)]
+    #[allow(dead_code)]
+    enum Command {
+        #[capnp(id = 1)]
+        Stop,
+        Move {
+            #[capnp(id = 2)]
+            x: i32,
+            #[capnp(id = 3)]
+            y: i32,
+        },
+    }
+
+    #[test]
+    fn test_tagged_enum() {
+        let output = Command::get_capnp_schema().render().unwrap();
+
+        assert_eq!(
+            output,
+            "struct Command {\n  tag @0 :UInt16;\n  union {\n    stop @1 :Void;\n    \
+             move :group {\n      x @2 :Int32;\n      y @3 :Int32;\n    }\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_render_enums_together() {
+        let output =
+            schema_from_items(&[Light::get_capnp_schema(), Presence::get_capnp_schema()]).unwrap();
+
+        assert_eq!(
+            output,
+            "struct Light {\n  union {\n    red @1 :Void;\n    green @0 :Void;\n  }\n}\n\n\
+             struct Presence {\n  union {\n    online @0 :Void;\n    away :group {\n      field0 @1 :Text;\n    }\n  }\n}\n"
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Member {
+        #[capnp(id = 0)]
+        presence: Presence,
+    }
+
+    #[test]
+    fn test_enum_reference_kind() {
+        let mut schema = Schema::new();
+        schema.extend([Presence::get_capnp_schema(), Member::get_capnp_schema()]);
+
+        let SchemaItem::Struct(member) = &schema.items[1] else {
+            panic!("Expected a struct");
+        };
+        let CapnpType::UserDefined(name) = &member.fields[0].field_type else {
+            panic!("Expected a user-defined reference");
+        };
+        // Rust enums render as structs containing a union, but are still enums
+        assert_eq!(schema.kind_of(name), Some(TypeKind::Enum));
+        assert_eq!(schema.kind_of("Member"), Some(TypeKind::Struct));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Peer {
+        #[capnp(id = 0)]
+        v4: std::net::Ipv4Addr,
+        #[capnp(id = 1)]
+        v6: std::net::Ipv6Addr,
+    }
+
+    #[test]
+    fn test_ip_address_types() {
+        let output = Peer::get_capnp_schema().render().unwrap();
+
+        assert!(output.contains("v4 @0 :UInt32;"));
+        assert!(output.contains("v6 @1 :Data;"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Settings {
+        #[capnp(id = 0, default = 10)]
+        retries: u32,
+        #[capnp(id = 1, default = "localhost")]
+        host: String,
+        #[capnp(id = 2, default = -1.5)]
+        offset: f64,
+        #[capnp(id = 3, default = -3)]
+        delta: i8,
+        #[capnp(id = 4, default = 18446744073709551615)]
+        limit: u64,
+    }
+
+    #[test]
+    fn test_default_values() {
+        let output = Settings::get_capnp_schema().render().unwrap();
+
+        assert_eq!(
+            output,
+            "struct Settings {\n  retries @0 :UInt32 = 10;\n  host @1 :Text = \"localhost\";\n  offset @2 :Float64 = -1.5;\n  delta @3 :Int8 = -3;\n  limit @4 :UInt64 = 18446744073709551615;\n}\n"
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Renamed {
+        #[capnp(id = 0)]
+        full_name: String,
+        #[capnp(id = 1, name = "years")]
+        age: u8,
+        #[capnp(id = 2)]
+        email: String,
+    }
+
+    #[test]
+    fn test_rust_name_comments() {
+        let options = RenderOptions {
+            rust_name_comments: true,
+            ..RenderOptions::default()
+        };
+        let mut schema = Schema::new();
+        schema.add_item(Renamed::get_capnp_schema());
+        let output = schema.render_with(&options).unwrap();
+
+        assert_eq!(
+            output,
+            "struct Renamed {\n  fullName @0 :Text;  # rust: full_name\n  years @1 :UInt8;  # rust: age\n  email @2 :Text;\n}\n"
+        );
+        assert!(!Renamed::get_capnp_schema().render().unwrap().contains('#'));
+    }
+
+    #[derive(CapnpType)]
+    #[capnp(type_id = 0xd4c9b59b3b3e6c7a)]
+    #[allow(dead_code)]
+    struct Pinned {
+        #[capnp(id = 0)]
+        value: u32,
+    }
+
+    #[test]
+    fn test_pinned_type_id() {
+        let output = Pinned::get_capnp_schema().render().unwrap();
+
+        assert_eq!(
+            output,
+            "struct Pinned @0xd4c9b59b3b3e6c7a {\n  value @0 :UInt32;\n}\n"
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Survey {
+        #[capnp(id = 0)]
+        answers: Vec<Option<u32>>,
+        #[capnp(id = 1)]
+        comments: Option<Vec<String>>,
+        #[capnp(id = 2)]
+        grid: Vec<Vec<Option<bool>>>,
+    }
+
+    #[test]
+    fn test_nested_options() {
+        let output = schema_for_item(&Survey::get_capnp_schema()).unwrap();
+
+        assert!(output.contains("answers @0 :List(OptionUInt32);"));
+        assert!(output.contains("comments @1 :OptionListText;"));
+        assert!(output.contains("grid @2 :List(List(OptionBool));"));
+        assert!(output.contains(
+            "struct OptionBool {\n  union {\n    none @0 :Void;\n    some @1 :Bool;\n  }\n}\n"
+        ));
+        assert!(output.contains("struct OptionListText {"));
+        assert!(output.contains("struct OptionUInt32 {"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Upload {
+        #[capnp(id = 0, as = "data")]
+        base64: String,
+        #[capnp(id = 1, as = "text")]
+        utf8: Vec<u8>,
+        #[capnp(id = 2)]
+        name: String,
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Attachment {
+        #[capnp(id = 0)]
+        body: Vec<u8>,
+        #[capnp(id = 1)]
+        thumbnail: Option<Vec<u8>>,
+    }
+
+    #[test]
+    fn test_byte_vectors_are_data() {
+        let output = schema_for_item(&Attachment::get_capnp_schema()).unwrap();
+
+        assert!(output.contains("body @0 :Data;"));
+        assert!(output.contains("thumbnail @1 :OptionData;"));
+        assert!(output.contains("some @1 :Data;"));
+        assert!(!output.contains("UInt8"));
+    }
+
+    #[derive(CapnpType)]
+    #[capnp(raw = "enum Level { low @0; high @1; }")]
+    #[capnp(raw = "level @1 :Level;")]
+    #[allow(dead_code)]
+    struct Alarm {
+        #[capnp(id = 0)]
+        message: String,
+    }
+
+    #[test]
+    fn test_raw_lines() {
+        let output = schema_for_item(&Alarm::get_capnp_schema()).unwrap();
+
+        assert_eq!(
+            output,
+            "struct Alarm {\n  message @0 :Text;\n  enum Level { low @0; high @1; }\n  \
+             level @1 :Level;\n}\n"
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Ticket {
+        #[capnp(id = 0, deprecated)]
+        priority: u8,
+        #[capnp(id = 1)]
+        title: String,
+    }
+
+    #[test]
+    fn test_deprecated_field() {
+        let output = schema_for_item(&Ticket::get_capnp_schema()).unwrap();
+
+        assert!(output.contains("priority @0 :UInt8;  # deprecated\n"));
+        assert!(output.contains("title @1 :Text;\n"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Tally {
+        #[capnp(id = 0)]
+        markers: Vec<()>,
+        #[capnp(id = 1)]
+        batches: Vec<Vec<()>>,
+        #[capnp(id = 2)]
+        pending: Option<()>,
+    }
+
+    #[test]
+    fn test_void_elements() {
+        let item = Tally::get_capnp_schema();
+        assert_eq!(item.validate(), Ok(()));
+
+        let output = schema_for_item(&item).unwrap();
+        assert!(output.contains("markers @0 :List(Void);"));
+        assert!(output.contains("batches @1 :List(List(Void));"));
+        assert!(output.contains("pending @2 :OptionVoid;"));
+        assert!(output.contains("some @1 :Void;"));
+    }
+
+    #[derive(CapnpType)]
+    #[capnp(
+        file = "target/test-schemas/sensor.capnp",
+        file_id = 0xd1c2a3b4e5f60718
+    )]
+    #[allow(dead_code)]
+    struct SensorReading {
+        #[capnp(id = 0)]
+        celsius: f32,
+    }
+
+    #[test]
+    fn test_one_shot_schema_file() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("target/test-schemas/sensor.capnp");
+        let content = std::fs::read_to_string(path).unwrap();
+
+        assert_eq!(
+            content,
+            "@0xd1c2a3b4e5f60718;\n\nstruct SensorReading {\n  celsius @0 :Float32;\n}\n"
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Packet {
+        #[capnp(id = 0, type = "UInt16")]
+        port: u32,
+        #[capnp(id = 1, type = "List(Data)")]
+        chunks: Vec<String>,
+    }
+
+    #[test]
+    fn test_type_override() {
+        let output = schema_for_item(&Packet::get_capnp_schema()).unwrap();
+
+        assert!(output.contains("port @0 :UInt16;"));
+        assert!(output.contains("chunks @1 :List(Data);"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Archive {
+        #[capnp(id = 0)]
+        chunks: Vec<Vec<u8>>,
+        #[capnp(id = 1, group = "checksum")]
+        digest: Vec<u8>,
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    enum Payload {
+        #[capnp(id = 0)]
+        Empty,
+        Raw(#[capnp(id = 1)] Vec<u8>),
+    }
+
+    #[test]
+    fn test_data_composes() {
+        let output = schema_for_item(&Archive::get_capnp_schema()).unwrap();
+        assert!(output.contains("chunks @0 :List(Data);"));
+        assert!(output.contains("checksum :group {\n    digest @1 :Data;\n  }"));
+
+        let output = schema_for_item(&Payload::get_capnp_schema()).unwrap();
+        assert!(output.contains("raw :group {\n      field0 @1 :Data;\n    }"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    enum Login {
+        #[capnp(id = 0)]
+        Guest,
+        Profile {
+            #[capnp(id = 1)]
+            name: String,
+            #[capnp(id = 2)]
+            avatar: Option<Vec<u8>>,
+        },
+    }
+
+    #[test]
+    fn test_option_in_variant_group() {
+        let output = schema_for_item(&Login::get_capnp_schema()).unwrap();
+
+        assert!(output.contains(
+            "profile :group {\n      name @1 :Text;\n      avatar @2 :OptionData;\n    }"
+        ));
+        assert!(output.contains(
+            "struct OptionData {\n  union {\n    none @0 :Void;\n    some @1 :Data;\n  }\n}\n"
+        ));
+    }
+
+    #[test]
+    fn test_as_override() {
+        let output = Upload::get_capnp_schema().render().unwrap();
+
+        assert!(output.contains("base64 @0 :Data;"));
+        assert!(output.contains("utf8 @1 :Text;"));
+        assert!(output.contains("name @2 :Text;"));
+    }
+
+    const DIGEST_LEN: usize = 32;
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Fingerprint {
+        #[capnp(id = 0)]
+        key: [u8; 16],
+        #[capnp(id = 1)]
+        digest: [u8; DIGEST_LEN],
+        #[capnp(id = 2)]
+        samples: [u32; 4],
+    }
+
+    #[test]
+    fn test_fixed_size_arrays() {
+        let output = Fingerprint::get_capnp_schema().render().unwrap();
+
+        assert!(output.contains("key @0 :Data;"));
+        assert!(output.contains("digest @1 :Data;"));
+        assert!(output.contains("samples @2 :List(UInt32);"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Roster<'a> {
+        #[capnp(id = 0)]
+        members: Vec<Member>,
+        #[capnp(id = 1)]
+        podium: [Member; 3],
+        #[capnp(id = 2)]
+        guests: &'a [Member],
+    }
+
+    #[test]
+    fn test_sequences_of_user_types() {
+        let output = Roster::get_capnp_schema().render().unwrap();
+
+        assert!(output.contains("members @0 :List(Member);"));
+        assert!(output.contains("podium @1 :List(Member);"));
+        assert!(output.contains("guests @2 :List(Member);"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct View<'a> {
+        #[capnp(id = 0)]
+        name: &'a str,
+        #[capnp(id = 1)]
+        data: &'a [u8],
+        #[capnp(id = 2)]
+        counts: &'a [u32],
+        #[capnp(id = 3)]
+        owner: &'a Member,
+    }
+
+    #[test]
+    fn test_reference_fields() {
+        let output = schema_for_item(&View::get_capnp_schema()).unwrap();
+
+        assert!(output.contains("name @0 :Text;"));
+        assert!(output.contains("data @1 :Data;"));
+        assert!(output.contains("counts @2 :List(UInt32);"));
+        assert!(output.contains("owner @3 :Member;"));
+    }
+
+    #[derive(CapnpType)]
+    #[capnp(strict)]
+    #[allow(dead_code)]
+    struct Checked<'a, T> {
+        #[capnp(id = 0)]
+        member: Member,
+        #[capnp(id = 1)]
+        history: Vec<Option<Presence>>,
+        #[capnp(id = 2)]
+        borrowed: &'a [Borrowed<'a>],
+        #[capnp(id = 3)]
+        envelope: Envelope<T>,
+    }
+
+    #[test]
+    fn test_strict_mode_accepts_derived_types() {
+        let output = Checked::<u32>::get_capnp_schema().render().unwrap();
+
+        assert!(output.contains("member @0 :Member;"));
+        assert!(output.contains("borrowed @2 :List(Borrowed);"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Borrowed<'a> {
+        #[capnp(id = 0)]
+        name: &'a str,
+        #[capnp(id = 1)]
+        title: Cow<'a, str>,
+        #[capnp(id = 2)]
+        aliases: Vec<&'a str>,
+    }
+
+    #[test]
+    fn test_borrowed_strings() {
+        let output = Borrowed::get_capnp_schema().render().unwrap();
+
+        assert!(output.contains("name @0 :Text;"));
+        assert!(output.contains("title @1 :Text;"));
+        assert!(output.contains("aliases @2 :List(Text);"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Reserved {
+        #[capnp(id = 0)]
+        value: u32,
+        #[capnp(id = 1)]
+        marker: (),
+    }
+
+    #[test]
+    fn test_unit_field_is_void() {
+        let output = Reserved::get_capnp_schema().render().unwrap();
+
+        assert!(output.contains("marker @1 :Void;"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct HexIds {
+        #[capnp(id = 0x0A)]
+        hex: u8,
+        #[capnp(id = 0o13)]
+        octal: u8,
+        #[capnp(id = 12)]
+        decimal: u8,
+    }
+
+    #[test]
+    fn test_hex_and_octal_ids() {
+        let SchemaItem::Struct(s) = HexIds::get_capnp_schema() else {
+            panic!("Expected a struct");
+        };
+        let ids: Vec<u32> = s.fields.iter().map(|field| field.id).collect();
+
+        assert_eq!(ids, vec![10, 11, 12]);
+    }
+
+    #[derive(CapnpType)]
+    #[capnp(embed_schema)]
+    #[allow(dead_code)]
+    struct Embedded {
+        #[capnp(id = 0)]
+        label: String,
+        #[capnp(id = 1)]
+        weight: Option<u16>,
+    }
+
+    #[test]
+    fn test_embedded_schema_constant() {
+        assert!(Embedded::CAPNP_SCHEMA.contains("struct Embedded {"));
+        assert_eq!(
+            Embedded::CAPNP_SCHEMA,
+            schema_for_item(&Embedded::get_capnp_schema()).unwrap()
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Outcome {
+        #[capnp(id = 0)]
+        result: Result<u32, String>,
+        #[capnp(id = 1)]
+        ack: Result<(), String>,
+    }
+
+    #[test]
+    fn test_result_fields() {
+        let output = schema_for_item(&Outcome::get_capnp_schema()).unwrap();
+
+        assert!(output.contains("result @0 :ResultUInt32Text;"));
+        assert!(output.contains("ack @1 :ResultVoidText;"));
+        assert!(output.contains(
+            "struct ResultUInt32Text {\n  union {\n    ok @0 :UInt32;\n    err @1 :Text;\n  }\n}\n"
+        ));
+        assert!(output.contains("ok @0 :Void;"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Sprite {
+        #[capnp(id = 0)]
+        name: String,
+        #[capnp(group, ids = [1, 2])]
+        position: (f32, f32),
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Marker {
+        #[capnp(id = 1, group = "position")]
+        x: f32,
+        #[capnp(id = 0)]
+        label: String,
+        #[capnp(id = 2, group = "position")]
+        y: f32,
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Pin(
+        #[capnp(id = 0)] String,
+        #[capnp(id = 1, group = "position")] f32,
+        #[capnp(id = 2, group = "position")] f32,
+    );
+
+    #[test]
+    fn test_attribute_group() {
+        let output = Marker::get_capnp_schema().render().unwrap();
+
+        assert_eq!(
+            output,
+            "struct Marker {\n  label @0 :Text;\n  position :group {\n    x @1 :Float32;\n    y @2 :Float32;\n  }\n}\n"
+        );
+        assert_eq!(
+            Pin::get_capnp_schema().render().unwrap(),
+            "struct Pin {\n  field0 @0 :Text;\n  position :group {\n    field1 @1 :Float32;\n    field2 @2 :Float32;\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_tuple_group() {
+        let output = Sprite::get_capnp_schema().render().unwrap();
+
+        assert_eq!(
+            output,
+            "struct Sprite {\n  name @0 :Text;\n  position :group {\n    field0 @1 :Float32;\n    field1 @2 :Float32;\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_generic_struct() {
+        let output = Envelope::<u32>::get_capnp_schema().render().unwrap();
+
+        assert!(output.starts_with("struct Envelope(T) {"));
+        assert!(output.contains("payload @0 :T;"));
+        assert!(output.contains("sender @1 :Text;"));
+        assert!(output.contains("history @2 :List(T);"));
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct A {
+        #[capnp(id = 0)]
+        b: Box<B>,
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct B {
+        #[capnp(id = 0)]
+        a: Box<A>,
+        #[capnp(id = 1)]
+        siblings: Vec<B>,
+    }
+
+    #[test]
+    fn test_mutually_recursive_types() {
+        let pool = [A::get_capnp_schema(), B::get_capnp_schema()];
+        let output = Schema::with_dependencies(&["A"], &pool).render().unwrap();
+
+        assert_eq!(
+            output,
+            "struct A {\n  b @0 :B;\n}\n\nstruct B {\n  a @0 :A;\n  siblings @1 :List(B);\n}\n"
+        );
+    }
+
+    #[derive(CapnpType)]
+    #[allow(dead_code)]
+    struct Frozen {
+        #[capnp(id = 0)]
+        label: Box<str>,
+        #[capnp(id = 1)]
+        samples: Box<[u32]>,
+        #[capnp(id = 2)]
+        raw: Box<[u8]>,
+    }
+
+    #[test]
+    fn test_boxed_str_and_slices() {
+        let output = schema_for_item(&Frozen::get_capnp_schema()).unwrap();
+
+        assert!(output.contains("label @0 :Text;"));
+        assert!(output.contains("samples @1 :List(UInt32);"));
+        assert!(output.contains("raw @2 :Data;"));
+    }
+}